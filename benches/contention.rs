@@ -0,0 +1,84 @@
+//! Compares counters, meters and moving averages under contention.
+//!
+//! Run with `cargo bench --bench contention`.
+
+extern crate metrics;
+extern crate syncbox;
+extern crate time;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use syncbox::atomic::{AtomicI64, Ordering};
+
+use metrics::ewma::EWMA;
+use metrics::meter::{Meter, StdMeter};
+use metrics::striped::StripedCounter;
+
+const THREADS: usize = 16;
+const ITERATIONS: usize = 1000000;
+
+fn contended<F>(name: &str, op: F)
+    where F: Fn() + Send + Sync + 'static
+{
+    let op = Arc::new(op);
+    let start = time::precise_time_ns();
+
+    let threads: Vec<_> = (0..THREADS)
+                              .map(|_| {
+                                  let op = op.clone();
+                                  thread::spawn(move || {
+                                      for _ in 0..ITERATIONS {
+                                          op();
+                                      }
+                                  })
+                              })
+                              .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    let elapsed = time::precise_time_ns() - start;
+    let ops = (THREADS * ITERATIONS) as u64;
+
+    println!("{:<16} {:>8.2} ns/op ({} threads)", name, elapsed as f64 / ops as f64, THREADS);
+}
+
+fn main() {
+    let atomic = Arc::new(AtomicI64::new(0));
+    contended("atomic", move || {
+        atomic.fetch_add(1, Ordering::SeqCst);
+    });
+
+    let striped = Arc::new(StripedCounter::new());
+    contended("striped", move || {
+        striped.inc(1);
+    });
+
+    let meter = Arc::new(StdMeter::new());
+    contended("meter", move || {
+        meter.mark(1);
+    });
+
+    // Reading a rate behind a lock, as EWMA used to, for comparison.
+    let locked = Arc::new(Mutex::new(0f64));
+    contended("mutex rate", move || {
+        let _ = *locked.lock().unwrap() * 1e9;
+    });
+
+    let ewma = Arc::new(EWMA::m01rate());
+    contended("ewma rate", move || {
+        let _ = ewma.rate();
+    });
+
+    let ewma = Arc::new(EWMA::m01rate());
+    contended("ewma tick", move || {
+        ewma.tick();
+    });
+
+    let meter = Arc::new(StdMeter::new());
+    contended("meter rates", move || {
+        let _ = meter.m01rate() + meter.m05rate() + meter.m15rate();
+    });
+}
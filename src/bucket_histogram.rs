@@ -0,0 +1,184 @@
+use std::f64;
+
+use syncbox::atomic::{AtomicI64, Ordering};
+
+use metric::{Metric, MetricValue};
+
+/// Returns `count` upper bounds, the first one being `start` and each following one `width`
+/// larger than the previous.
+///
+/// # Panics
+///
+/// Panics if count is zero or width <= 0.
+pub fn linear_buckets(start: f64, width: f64, count: usize) -> Vec<f64> {
+    assert!(count > 0);
+    assert!(width > 0.0);
+
+    (0..count).map(|i| start + width * i as f64).collect()
+}
+
+/// Returns `count` upper bounds, the first one being `start` and each following one `factor`
+/// times the previous.
+///
+/// # Panics
+///
+/// Panics if count is zero, start <= 0 or factor <= 1.
+pub fn exponential_buckets(start: f64, factor: f64, count: usize) -> Vec<f64> {
+    assert!(count > 0);
+    assert!(start > 0.0);
+    assert!(factor > 1.0);
+
+    (0..count).map(|i| start * factor.powi(i as i32)).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct BucketSnapshot {
+    /// Pairs of upper bounds and the number of values less than or equal to them, ending with
+    /// an infinite bound which counts every value.
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    pub sum: f64
+}
+
+/// A histogram which counts values into buckets with fixed upper bounds.
+///
+/// Unlike percentiles, bucket counts from several instances can be added together, so such
+/// histograms can be aggregated after reporting.
+pub struct BucketHistogram {
+    bounds: Vec<f64>,
+    // One count per bound, plus one for the values above the last bound.
+    counts: Vec<AtomicI64>,
+    // Bits of the f64 sum.
+    sum: AtomicI64,
+}
+
+impl BucketHistogram {
+    /// Creates a new histogram with the given upper bounds.
+    ///
+    /// An infinite upper bound is always added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bounds are not strictly increasing.
+    pub fn new(mut bounds: Vec<f64>) -> BucketHistogram {
+        if bounds.last().map_or(false, |&b| b == f64::INFINITY) {
+            bounds.pop();
+        }
+
+        assert!(bounds.windows(2).all(|w| w[0] < w[1]),
+                "bucket bounds must be strictly increasing");
+
+        BucketHistogram {
+            counts: (0..bounds.len() + 1).map(|_| AtomicI64::new(0)).collect(),
+            bounds: bounds,
+            sum: AtomicI64::new(0f64.to_bits() as i64),
+        }
+    }
+
+    /// Records a value into the first bucket whose upper bound is not below it.
+    pub fn observe(&self, value: f64) {
+        let i = self.bounds.iter().position(|&bound| value <= bound).unwrap_or(self.bounds.len());
+        self.counts[i].fetch_add(1, Ordering::SeqCst);
+
+        loop {
+            let old = self.sum.load(Ordering::SeqCst);
+            let new = (f64::from_bits(old as u64) + value).to_bits() as i64;
+
+            if self.sum.compare_and_swap(old, new, Ordering::SeqCst) == old {
+                break;
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> BucketSnapshot {
+        let mut cumulative = 0;
+
+        let buckets = self.bounds
+                          .iter()
+                          .cloned()
+                          .chain(Some(f64::INFINITY))
+                          .zip(&self.counts)
+                          .map(|(bound, count)| {
+                              cumulative += count.load(Ordering::SeqCst) as u64;
+                              (bound, cumulative)
+                          })
+                          .collect();
+
+        BucketSnapshot {
+            buckets: buckets,
+            count: cumulative,
+            sum: f64::from_bits(self.sum.load(Ordering::SeqCst) as u64),
+        }
+    }
+}
+
+impl Metric for BucketHistogram {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Buckets(self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64;
+
+    use metric::{Metric, MetricValue};
+    use super::*;
+
+    #[test]
+    fn linear() {
+        assert_eq!(vec![1.0, 3.0, 5.0], linear_buckets(1.0, 2.0, 3));
+    }
+
+    #[test]
+    fn exponential() {
+        assert_eq!(vec![1.0, 10.0, 100.0, 1000.0], exponential_buckets(1.0, 10.0, 4));
+    }
+
+    #[test]
+    #[should_panic]
+    fn exponential_invalid_factor() {
+        exponential_buckets(1.0, 1.0, 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn not_increasing() {
+        BucketHistogram::new(vec![1.0, 1.0]);
+    }
+
+    #[test]
+    fn cumulative_counts() {
+        let h = BucketHistogram::new(vec![1.0, 5.0, 10.0]);
+
+        for &v in [0.5, 1.0, 2.0, 5.0, 7.0, 100.0].iter() {
+            h.observe(v);
+        }
+
+        let s = h.snapshot();
+
+        assert_eq!(vec![(1.0, 2), (5.0, 4), (10.0, 5), (f64::INFINITY, 6)], s.buckets);
+        assert_eq!(6, s.count);
+        assert_eq!(115.5, s.sum);
+    }
+
+    #[test]
+    fn explicit_infinite_bound() {
+        let h = BucketHistogram::new(vec![1.0, f64::INFINITY]);
+
+        assert_eq!(2, h.snapshot().buckets.len());
+    }
+
+    #[test]
+    fn export() {
+        let h = BucketHistogram::new(linear_buckets(0.0, 10.0, 3));
+        h.observe(15.0);
+
+        match h.export_metric() {
+            MetricValue::Buckets(s) => {
+                assert_eq!(vec![(0.0, 0), (10.0, 0), (20.0, 1), (f64::INFINITY, 1)], s.buckets)
+            }
+            _ => panic!("expected buckets"),
+        }
+    }
+}
@@ -3,34 +3,41 @@ use registry::{Registry, StdRegistry};
 use std::thread;
 use std::sync::Arc;
 use meter::Meter;
-use reporter::Reporter;
+use reporter::{Reporter, TimestampSource, WallClock};
+use bucket_histogram::BucketSnapshot;
 use counter::StdCounter;
-use gauge::StdGauge;
+use ddsketch::DDSketch;
+use exponential_histogram::ExponentialSnapshot;
+use gauge::GaugeValue;
 use meter::MeterSnapshot;
+use rolling::RollingSnapshot;
+use timer::TimerSnapshot;
 use std::time::Duration;
-use histogram::Histogram;
+use snapshot::{HistogramSnapshot, PERCENTILES};
+use summary::SummarySnapshot;
 use carbon_sender::Carbon;
-use time;
 use time::Timespec;
 
-pub struct CarbonReporter {
+pub struct CarbonReporter<T: TimestampSource = WallClock> {
     host_and_port: String,
     prefix: &'static str,
     registry: Arc<StdRegistry<'static>>,
-    reporter_name: &'static str
+    reporter_name: &'static str,
+    timestamps: Arc<T>
 }
 
-impl Reporter for CarbonReporter {
+impl<T: TimestampSource + 'static> Reporter for CarbonReporter<T> {
     fn report<'report>(&self, delay_ms: u32) {
-        use metric::MetricValue::{Counter, Gauge, Histogram, Meter};
+        use metric::MetricValue::{Buckets, Counter, Exponential, Gauge, Histogram, Meter, Rolling, Sketch, Summary, Timer};
 
         let prefix = self.prefix;
         let host_and_port = self.host_and_port.clone();
         let mut carbon = Carbon::new(host_and_port);
         let registry = self.registry.clone();
+        let timestamps = self.timestamps.clone();
         thread::spawn(move || {
                                loop {
-                                   let ts = time::now().to_timespec();
+                                   let ts = timestamps.timestamp();
                                    for metric_name in &registry.get_metrics_names() {
                                        let metric = registry.get(metric_name);
                                        let mnas = metric_name.to_string(); // Metric name as string
@@ -38,7 +45,13 @@ impl Reporter for CarbonReporter {
                                            Meter(x) => send_meter_metric(mnas, x, & mut carbon,  prefix, ts),
                                            Gauge(x) => send_gauge_metric(mnas, x, & mut carbon,  prefix, ts),
                                            Counter(x) => send_counter_metric(mnas, x, & mut carbon, prefix, ts),
-                                           Histogram(mut x) => send_histogram_metric(mnas, & mut x, & mut carbon,  prefix, ts),
+                                           Histogram(x) => send_histogram_metric(mnas, x, & mut carbon,  prefix, ts),
+                                           Timer(x) => send_timer_metric(mnas, x, & mut carbon, prefix, ts),
+                                           Buckets(x) => send_buckets_metric(mnas, x, & mut carbon, prefix, ts),
+                                           Exponential(x) => send_exponential_metric(mnas, x, & mut carbon, prefix, ts),
+                                           Sketch(x) => send_sketch_metric(mnas, x, & mut carbon, prefix, ts),
+                                           Summary(x) => send_summary_metric(mnas, x, & mut carbon, prefix, ts),
+                                           Rolling(x) => send_rolling_metric(mnas, x, & mut carbon, prefix, ts),
                                        }
                                    }
                                    thread::sleep(Duration::from_millis(delay_ms as u64));
@@ -62,25 +75,22 @@ fn send_meter_metric( metric_name: String,
      ts: Timespec) {
 
     let count = meter.count.to_string();
-    let m1_rate = meter.rates[0].to_string();
-    let m5_rate = meter.rates[1].to_string();
-    let m15_rate = meter.rates[2].to_string();
     let mean_rate = meter.mean.to_string();
     carbon.write(prefix(format!("{}.count", metric_name), prefix_str), count, ts);
-    carbon.write(prefix(format!("{}.m1", metric_name), prefix_str), m1_rate, ts);
-    carbon.write(prefix(format!("{}.m5", metric_name), prefix_str), m5_rate, ts);
-    carbon.write(prefix(format!("{}.m15", metric_name), prefix_str), m15_rate, ts);
+    for &(ref label, rate) in meter.rates.iter() {
+        carbon.write(prefix(format!("{}.{}", metric_name, label), prefix_str), rate.to_string(), ts);
+    }
     carbon.write(prefix(format!("{}.mean", metric_name), prefix_str), mean_rate, ts);
 }
 
 fn send_gauge_metric(metric_name: String,
-     gauge: StdGauge,
+     gauge: GaugeValue,
      carbon:&mut Carbon,
      prefix_str: & 'static str,
      ts: Timespec) {
          carbon
          .write(prefix(format!("{}", metric_name), prefix_str),
-         gauge.value.to_string(),
+         gauge.to_string(),
           ts);
 }
 
@@ -95,83 +105,185 @@ fn send_counter_metric(metric_name: String,
         ts);
 }
 fn send_histogram_metric(metric_name: String,
-    histogram:& mut Histogram,
+    histogram: HistogramSnapshot,
     carbon:& mut Carbon,
     prefix_str: & 'static str,
     ts: Timespec) {
-        let count = histogram.count();
-        //let sum = histogram.sum();
-        //let mean = sum / count;
-        let max = histogram.percentile(100.0).unwrap();
-        let min = histogram.percentile(0.0).unwrap();
-
-        let p50 = histogram.percentile(50.0).unwrap();
-        let p75 = histogram.percentile(75.0).unwrap();
-        let p95 = histogram.percentile(95.0).unwrap();
-        let p98 = histogram.percentile(98.0).unwrap();
-        let p99 = histogram.percentile(99.0).unwrap();
-        let p999 = histogram.percentile(99.9).unwrap();
-        let p9999 = histogram.percentile(99.99).unwrap();
-        let p99999 = histogram.percentile(99.999).unwrap();
-
         carbon
         .write(prefix(format!("{}.count", metric_name), prefix_str),
-        count.to_string(),
+        histogram.count().to_string(),
+        ts);
+
+        carbon
+        .write(prefix(format!("{}.sum", metric_name), prefix_str),
+        histogram.sum().to_string(),
         ts);
 
         carbon
         .write(prefix(format!("{}.max", metric_name), prefix_str),
-        max.to_string(),
+        histogram.max().to_string(),
         ts);
 
-          //carbon
-          //.write(prefix(format!("{}.mean", metric_name), prefix_str),
-          //mean.into_string(),
-          // ts);
+        carbon
+        .write(prefix(format!("{}.mean", metric_name), prefix_str),
+        histogram.mean().to_string(),
+        ts);
 
         carbon
         .write(prefix(format!("{}.min", metric_name), prefix_str),
-        min.to_string(),
+        histogram.min().to_string(),
+        ts);
+
+        carbon
+        .write(prefix(format!("{}.stddev", metric_name), prefix_str),
+        histogram.stddev().to_string(),
         ts);
 
+        for &(percentile, name) in PERCENTILES.iter() {
+            carbon
+            .write(prefix(format!("{}.{}", metric_name, name), prefix_str),
+            histogram.percentile(percentile).to_string(),
+            ts);
+        }
+}
+
+fn send_timer_metric(metric_name: String,
+    timer: TimerSnapshot,
+    carbon:& mut Carbon,
+    prefix_str: & 'static str,
+    ts: Timespec) {
+        send_meter_metric(format!("{}.rate", metric_name), timer.meter, carbon, prefix_str, ts);
+        send_histogram_metric(metric_name, timer.histogram, carbon, prefix_str, ts);
+}
+
+// Graphite separates path components with dots, so they can't appear within a bound.
+fn le_name(bound: f64) -> String {
+    if bound.is_infinite() {
+        "le_inf".to_string()
+    } else {
+        format!("le_{}", bound).replace(".", "_")
+    }
+}
+
+fn send_buckets_metric(metric_name: String,
+    buckets: BucketSnapshot,
+    carbon:& mut Carbon,
+    prefix_str: & 'static str,
+    ts: Timespec) {
+        carbon
+        .write(prefix(format!("{}.count", metric_name), prefix_str),
+        buckets.count.to_string(),
+        ts);
+
+        carbon
+        .write(prefix(format!("{}.sum", metric_name), prefix_str),
+        buckets.sum.to_string(),
+        ts);
+
+        for &(bound, count) in buckets.buckets.iter() {
+            carbon
+            .write(prefix(format!("{}.bucket.{}", metric_name, le_name(bound)), prefix_str),
+            count.to_string(),
+            ts);
+        }
+}
+
+fn send_exponential_metric(metric_name: String,
+    histogram: ExponentialSnapshot,
+    carbon:& mut Carbon,
+    prefix_str: & 'static str,
+    ts: Timespec) {
+        carbon
+        .write(prefix(format!("{}.max", metric_name), prefix_str),
+        histogram.max().to_string(),
+        ts);
+
+        carbon
+        .write(prefix(format!("{}.min", metric_name), prefix_str),
+        histogram.min().to_string(),
+        ts);
+
+        // The count, the sum and the percentiles are sent as a summary.
+        send_summary_metric(metric_name, histogram.summary(), carbon, prefix_str, ts);
+}
+
+fn send_sketch_metric(metric_name: String,
+    sketch: DDSketch,
+    carbon:& mut Carbon,
+    prefix_str: & 'static str,
+    ts: Timespec) {
         carbon
-        .write(prefix(format!("{}.p50", metric_name), prefix_str),
-        p50.to_string(),
+        .write(prefix(format!("{}.count", metric_name), prefix_str),
+        sketch.count().to_string(),
         ts);
 
         carbon
-        .write(prefix(format!("{}.p75", metric_name), prefix_str),
-        p75.to_string(),
+        .write(prefix(format!("{}.sum", metric_name), prefix_str),
+        sketch.sum().to_string(),
         ts);
 
         carbon
-        .write(prefix(format!("{}.p95", metric_name), prefix_str),
-        p95.to_string(),
+        .write(prefix(format!("{}.max", metric_name), prefix_str),
+        sketch.max().to_string(),
         ts);
 
         carbon
-        .write(prefix(format!("{}.p98", metric_name), prefix_str),
-        p98.to_string(),
+        .write(prefix(format!("{}.min", metric_name), prefix_str),
+        sketch.min().to_string(),
         ts);
 
+        for &(percentile, name) in PERCENTILES.iter() {
+            carbon
+            .write(prefix(format!("{}.{}", metric_name, name), prefix_str),
+            sketch.percentile(percentile).to_string(),
+            ts);
+        }
+}
+
+// Names a quantile the way PERCENTILES does, e.g. 0.999 as p999.
+fn quantile_name(quantile: f64) -> String {
+    let percent = format!("{:.6}", quantile * 100.0);
+    let percent = percent.trim_right_matches('0').trim_right_matches('.');
+
+    format!("p{}", percent.replace(".", ""))
+}
+
+fn send_summary_metric(metric_name: String,
+    summary: SummarySnapshot,
+    carbon:& mut Carbon,
+    prefix_str: & 'static str,
+    ts: Timespec) {
         carbon
-        .write(prefix(format!("{}.p99", metric_name), prefix_str),
-        p99.to_string(),
+        .write(prefix(format!("{}.count", metric_name), prefix_str),
+        summary.count.to_string(),
         ts);
 
         carbon
-        .write(prefix(format!("{}.p999", metric_name), prefix_str),
-        p999.to_string(),
+        .write(prefix(format!("{}.sum", metric_name), prefix_str),
+        summary.sum.to_string(),
         ts);
 
+        for &(quantile, value) in summary.quantiles.iter() {
+            carbon
+            .write(prefix(format!("{}.{}", metric_name, quantile_name(quantile)), prefix_str),
+            value.to_string(),
+            ts);
+        }
+}
+
+fn send_rolling_metric(metric_name: String,
+    rolling: RollingSnapshot,
+    carbon:& mut Carbon,
+    prefix_str: & 'static str,
+    ts: Timespec) {
         carbon
-        .write(prefix(format!("{}.p9999", metric_name), prefix_str),
-        p9999.to_string(),
+        .write(prefix(format!("{}.sum", metric_name), prefix_str),
+        rolling.sum().to_string(),
         ts);
 
         carbon
-        .write(prefix(format!("{}.p99999", metric_name), prefix_str),
-        p99999.to_string(),
+        .write(prefix(format!("{}.rate", metric_name), prefix_str),
+        rolling.rate().to_string(),
         ts);
 }
 
@@ -180,11 +292,24 @@ impl CarbonReporter {
      reporter_name: &'static str,
      host_and_port: String,
      prefix: &'static str) -> CarbonReporter {
+        CarbonReporter::with_timestamps(registry, reporter_name, host_and_port, prefix, WallClock)
+    }
+}
+
+impl<T: TimestampSource + 'static> CarbonReporter<T> {
+    /// Creates a new reporter which takes the timestamps of the metrics it sends from the given
+    /// source.
+    pub fn with_timestamps(registry: Arc<StdRegistry<'static>>,
+     reporter_name: &'static str,
+     host_and_port: String,
+     prefix: &'static str,
+     timestamps: T) -> CarbonReporter<T> {
         CarbonReporter {
             host_and_port: host_and_port,
             prefix: prefix,
             registry: registry,
-            reporter_name: reporter_name
+            reporter_name: reporter_name,
+            timestamps: Arc::new(timestamps)
         }
     }
 
@@ -199,8 +324,20 @@ mod test {
     use counter::{Counter, StdCounter};
     use gauge::{Gauge, StdGauge};
     use registry::{Registry, StdRegistry};
-    use carbon_reporter::CarbonReporter;
+    use reporter::TimestampSource;
+    use bucket_histogram::{BucketHistogram, exponential_buckets};
+    use carbon_reporter::{CarbonReporter, le_name, quantile_name};
+    use ddsketch::DDSketchHistogram;
+    use exponential_histogram::ExponentialHistogram;
+    use interval::IntervalHistogram;
+    use snapshot::PERCENTILES;
+    use rolling::RollingCounter;
+    use summary::{Quantile, Summary};
+    use time::Timespec;
+    use tdigest::TDigestHistogram;
+    use timer::Timer;
     use std::sync::Arc;
+    use std::time::Duration;
     use histogram::*;
 
     #[test]
@@ -220,15 +357,78 @@ mod test {
         max_value: 1000000,
         precision: 3,
 }).unwrap();
+        let i = IntervalHistogram::with_histogram(h.clone());
+        i.update(3);
+
+        let w = RollingCounter::new(Duration::from_secs(60));
+        w.inc(5);
+
         h.record(1, 1);
 
+        let t = Timer::new();
+        t.time(|| ());
+
+        let b = BucketHistogram::new(exponential_buckets(0.5, 2.0, 4));
+        b.observe(3.0);
+
+        let e = ExponentialHistogram::new();
+        e.observe(3.0);
+
+        let d = TDigestHistogram::new();
+        d.update(3);
+
+        let s = DDSketchHistogram::new();
+        s.observe(3.0);
+
+        let q = Summary::new(vec![Quantile::new(0.99, 0.001)]);
+        q.observe(3.0);
+
         let mut r = StdRegistry::new();
         r.insert("meter1", m);
         r.insert("counter1", c);
         r.insert("gauge1", g);
         r.insert("histogram", h);
+        r.insert("timer", t);
+        r.insert("buckets", b);
+        r.insert("exponential", e);
+        r.insert("tdigest", d);
+        r.insert("sketch", s);
+        r.insert("summary", q);
+        r.insert("interval", i);
+        r.insert("rolling", w);
 
         let arc_registry = Arc::new(r);
         CarbonReporter::new(arc_registry.clone(), "test", "localhost:0".to_string(), "asd.asdf");
     }
+
+    #[test]
+    fn bucket_names() {
+        assert_eq!("le_0_005", le_name(0.005));
+        assert_eq!("le_10", le_name(10.0));
+        assert_eq!("le_inf", le_name(::std::f64::INFINITY));
+    }
+
+    #[test]
+    fn fixed_timestamps() {
+        let r = CarbonReporter::with_timestamps(Arc::new(StdRegistry::new()),
+                                                "test",
+                                                "localhost:0".to_string(),
+                                                "asd.asdf",
+                                                || Timespec::new(1500000000, 250000000));
+
+        assert_eq!(Timespec::new(1500000000, 250000000), r.timestamps.timestamp());
+    }
+
+    #[test]
+    fn quantile_names() {
+        assert_eq!("p50", quantile_name(0.5));
+        assert_eq!("p99", quantile_name(0.99));
+        assert_eq!("p999", quantile_name(0.999));
+        assert_eq!("p5", quantile_name(0.05));
+
+        // Summaries of exponential histograms are sent under the usual percentile names.
+        for &(percentile, name) in PERCENTILES.iter() {
+            assert_eq!(name, quantile_name(percentile / 100.0));
+        }
+    }
 }
@@ -1,5 +1,7 @@
 extern crate num;
 
+use syncbox::atomic::{AtomicI64, Ordering};
+
 use metric::{Metric, MetricValue};
 
 #[derive(Copy, Clone, Debug)]
@@ -7,6 +9,12 @@ pub struct StdCounter {
     pub value: i64
 }
 
+/// The operations shared by every counter.
+///
+/// They take `&mut self`, since `StdCounter` is a plain value which needs a lock to be shared.
+/// This is kept on purpose so that existing counters keep working: `AtomicCounter` and
+/// `StripedCounter` implement it by delegating to their inherent methods, which take `&self` and
+/// are the ones to use from several threads.
 pub trait Counter{
     fn clear(&mut self);
 
@@ -47,8 +55,68 @@ impl StdCounter {
     }
 }
 
+/// A lock-free counter which can be shared between threads and updated through `&self`.
+pub struct AtomicCounter {
+    value: AtomicI64
+}
+
+impl AtomicCounter {
+    pub fn new() -> AtomicCounter {
+        AtomicCounter::with_value(0)
+    }
+
+    pub fn with_value(value: i64) -> AtomicCounter {
+        AtomicCounter { value: AtomicI64::new(value) }
+    }
+
+    pub fn clear(&self) {
+        self.value.store(0, Ordering::SeqCst);
+    }
+
+    pub fn dec(&self, value: i64) {
+        // Negating i64::MIN overflows, while wrapping it gives the same result once added.
+        self.value.fetch_add(value.wrapping_neg(), Ordering::SeqCst);
+    }
+
+    pub fn inc(&self, value: i64) {
+        self.value.fetch_add(value, Ordering::SeqCst);
+    }
+
+    pub fn value(&self) -> i64 {
+        self.value.load(Ordering::SeqCst)
+    }
+}
+
+impl Counter for AtomicCounter {
+    fn clear(&mut self) {
+        AtomicCounter::clear(self);
+    }
+
+    fn dec(&mut self, value: i64) {
+        AtomicCounter::dec(self, value);
+    }
+
+    fn inc(&mut self, value: i64) {
+        AtomicCounter::inc(self, value);
+    }
+
+    fn snapshot(self) -> AtomicCounter {
+        AtomicCounter::with_value(self.value())
+    }
+}
+
+impl Metric for AtomicCounter {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Counter(StdCounter { value: self.value() })
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use metric::{Metric, MetricValue};
     use super::*;
 
     #[test]
@@ -69,4 +137,75 @@ mod test {
         assert!(c.value == 0);
         assert!(c_snapshot.value == 1);
     }
+
+    #[test]
+    fn atomic_inc_dec_clear() {
+        let c = AtomicCounter::new();
+        c.inc(5);
+        c.dec(2);
+
+        assert_eq!(3, c.value());
+
+        c.clear();
+
+        assert_eq!(0, c.value());
+    }
+
+    #[test]
+    fn atomic_dec_min() {
+        let c = AtomicCounter::new();
+        c.dec(::std::i64::MIN);
+
+        assert_eq!(::std::i64::MIN, c.value());
+    }
+
+    #[test]
+    fn atomic_from_threads() {
+        let c = Arc::new(AtomicCounter::new());
+
+        let threads: Vec<_> = (0..8)
+                                  .map(|i| {
+                                      let c = c.clone();
+                                      thread::spawn(move || {
+                                          for _ in 0..1000 {
+                                              if i % 2 == 0 {
+                                                  c.inc(3);
+                                              } else {
+                                                  c.dec(1);
+                                              }
+                                          }
+                                      })
+                                  })
+                                  .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(8000, c.value());
+    }
+
+    #[test]
+    fn atomic_export() {
+        let c = AtomicCounter::with_value(42);
+
+        match c.export_metric() {
+            MetricValue::Counter(x) => assert_eq!(42, x.value),
+            _ => panic!("expected a counter"),
+        }
+    }
+
+    #[test]
+    fn atomic_send() {
+        fn checker<T: Send>(_: T) {}
+
+        checker(AtomicCounter::new());
+    }
+
+    #[test]
+    fn atomic_sync() {
+        fn checker<T: Sync>(_: T) {}
+
+        checker(AtomicCounter::new());
+    }
 }
@@ -0,0 +1,646 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::f64;
+use std::sync::Mutex;
+
+use metric::{Metric, MetricValue};
+
+const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+const DEFAULT_MAX_BINS: usize = 2048;
+// Finer accuracies would give the smallest and largest finite values bin indexes beyond an i32.
+const MIN_RELATIVE_ACCURACY: f64 = 1e-6;
+
+// The first byte of every encoded sketch, changed whenever the layout changes.
+const FORMAT_VERSION: u8 = 1;
+
+/// A DDSketch, a quantile sketch with a guaranteed relative error.
+///
+/// Values are counted into logarithmically sized bins: bin `i` holds the values in
+/// `(gamma^(i-1), gamma^i]`, where `gamma = (1 + a) / (1 - a)` for a relative accuracy `a`, and
+/// the negative values mirror it. Every quantile is then estimated within a factor of `a` of the
+/// exact value. Sketches with the same accuracy merge without any loss, by adding their bins.
+///
+/// The number of bins is bounded: when it's exceeded, the bins of the smallest absolute values are
+/// collapsed, so that only the lowest quantiles lose their guarantee.
+#[derive(Clone, Debug)]
+pub struct DDSketch {
+    relative_accuracy: f64,
+    gamma_ln: f64,
+    max_bins: usize,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl DDSketch {
+    /// Creates a new sketch with a relative accuracy of 1% and at most 2048 bins.
+    pub fn new() -> DDSketch {
+        DDSketch::with_accuracy(DEFAULT_RELATIVE_ACCURACY, DEFAULT_MAX_BINS)
+    }
+
+    /// Creates a new sketch with the given relative accuracy and maximum number of bins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the accuracy is not between 1e-6 inclusive and 1 exclusive, or if max_bins is
+    /// zero.
+    pub fn with_accuracy(relative_accuracy: f64, max_bins: usize) -> DDSketch {
+        assert!(relative_accuracy >= MIN_RELATIVE_ACCURACY && relative_accuracy < 1.0);
+        assert!(max_bins > 0);
+
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+
+        DDSketch {
+            relative_accuracy: relative_accuracy,
+            gamma_ln: gamma.ln(),
+            max_bins: max_bins,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Returns the smallest value, or zero if the sketch is empty.
+    pub fn min(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.min }
+    }
+
+    /// Returns the largest value, or zero if the sketch is empty.
+    pub fn max(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.max }
+    }
+
+    /// Returns the number of bins in use.
+    pub fn bins(&self) -> usize {
+        self.positive.len() + self.negative.len()
+    }
+
+    fn index(&self, value: f64) -> i32 {
+        (value.ln() / self.gamma_ln).ceil() as i32
+    }
+
+    // The point of the bin with a relative error of at most the accuracy to any value within it.
+    fn representative(&self, index: i32) -> f64 {
+        let gamma = self.gamma_ln.exp();
+
+        2.0 * (index as f64 * self.gamma_ln).exp() / (1.0 + gamma)
+    }
+
+    /// Adds a value. Infinite and NaN values are ignored.
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        if value == 0.0 {
+            self.zero_count += 1;
+            return;
+        }
+
+        let index = self.index(value.abs());
+        let bins = if value > 0.0 { &mut self.positive } else { &mut self.negative };
+        *bins.entry(index).or_insert(0) += 1;
+
+        self.collapse();
+    }
+
+    /// Adds every value of another sketch.
+    ///
+    /// Fails if the sketches don't have the same relative accuracy, as their bins wouldn't line
+    /// up.
+    pub fn merge(&mut self, other: &DDSketch) -> Result<(), &'static str> {
+        if self.relative_accuracy != other.relative_accuracy {
+            return Err("sketches with different relative accuracies can't be merged");
+        }
+
+        for (&i, &c) in &other.positive {
+            *self.positive.entry(i).or_insert(0) += c;
+        }
+        for (&i, &c) in &other.negative {
+            *self.negative.entry(i).or_insert(0) += c;
+        }
+
+        self.zero_count += other.zero_count;
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+
+        self.collapse();
+
+        Ok(())
+    }
+
+    // Merges the bins of the smallest absolute values until the sketch fits in max_bins.
+    fn collapse(&mut self) {
+        while self.bins() > self.max_bins {
+            let negative = self.negative.len() >= 2 &&
+                           (self.positive.len() < 2 ||
+                            self.negative.keys().next() <= self.positive.keys().next());
+
+            let bins = if negative {
+                &mut self.negative
+            } else {
+                &mut self.positive
+            };
+
+            let mut lowest = bins.keys().take(2);
+            let (first, second) = match (lowest.next(), lowest.next()) {
+                (Some(&first), Some(&second)) => (first, second),
+                _ => return,
+            };
+
+            let count = bins.remove(&first).unwrap_or(0);
+            *bins.entry(second).or_insert(0) += count;
+        }
+    }
+
+    /// Returns the value at the given percentile, from 0 to 100.
+    ///
+    /// The result is within the relative accuracy of a value of the sketch at that rank. Returns
+    /// zero if the sketch is empty.
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        let rank = cmp::max(1, (percentile / 100.0 * self.count as f64).ceil() as u64);
+
+        // The extremes are known exactly.
+        if rank == 1 {
+            return self.min;
+        }
+        if rank >= self.count {
+            return self.max;
+        }
+
+        let mut seen = 0;
+
+        let negative = self.negative.iter().rev().map(|(&i, &c)| (-self.representative(i), c));
+        let zero = Some((0.0, self.zero_count)).into_iter();
+        let positive = self.positive.iter().map(|(&i, &c)| (self.representative(i), c));
+
+        for (value, count) in negative.chain(zero).chain(positive) {
+            seen += count;
+
+            if seen >= rank {
+                return value.max(self.min).min(self.max);
+            }
+        }
+
+        self.max
+    }
+
+    /// Encodes the sketch into a compact binary format, which `decode` reads back.
+    ///
+    /// The format starts with a version byte, followed by the relative accuracy, the maximum
+    /// number of bins, the sum, the minimum and the maximum, the zero count, and then the
+    /// positive and the negative bins. Floats take 8 little endian bytes, while counts and bin
+    /// indexes are written as variable length integers, the indexes as zigzag encoded deltas from
+    /// the previous one.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(45 + 4 * self.bins());
+
+        buf.push(FORMAT_VERSION);
+        write_f64(&mut buf, self.relative_accuracy);
+        write_varint(&mut buf, self.max_bins as u64);
+        write_f64(&mut buf, self.sum);
+        write_f64(&mut buf, self.min);
+        write_f64(&mut buf, self.max);
+        write_varint(&mut buf, self.zero_count);
+        write_bins(&mut buf, &self.positive);
+        write_bins(&mut buf, &self.negative);
+
+        buf
+    }
+
+    /// Decodes a sketch written by `encode`.
+    pub fn decode(buf: &[u8]) -> Result<DDSketch, &'static str> {
+        let mut reader = Reader { buf: buf };
+
+        if try!(reader.byte()) != FORMAT_VERSION {
+            return Err("unsupported sketch format version");
+        }
+
+        let relative_accuracy = try!(reader.f64());
+        let max_bins = try!(reader.varint());
+
+        let valid_accuracy = relative_accuracy >= MIN_RELATIVE_ACCURACY && relative_accuracy < 1.0;
+        if !valid_accuracy || max_bins == 0 {
+            return Err("invalid sketch parameters");
+        }
+
+        let mut sketch = DDSketch::with_accuracy(relative_accuracy, max_bins as usize);
+        sketch.sum = try!(reader.f64());
+        sketch.min = try!(reader.f64());
+        sketch.max = try!(reader.f64());
+        sketch.zero_count = try!(reader.varint());
+        sketch.positive = try!(reader.bins());
+        sketch.negative = try!(reader.bins());
+
+        if !reader.buf.is_empty() {
+            return Err("trailing bytes after sketch");
+        }
+
+        // The sketch comes from another process, so its parts may not fit together.
+        if sketch.bins() as u64 > max_bins {
+            return Err("more sketch bins than allowed");
+        }
+
+        sketch.count = sketch.zero_count;
+        for &c in sketch.positive.values().chain(sketch.negative.values()) {
+            sketch.count = try!(sketch.count.checked_add(c).ok_or("sketch count overflow"));
+        }
+
+        if !sketch.sum.is_finite() {
+            return Err("invalid sketch totals");
+        }
+
+        if sketch.count == 0 {
+            sketch.min = f64::INFINITY;
+            sketch.max = f64::NEG_INFINITY;
+        } else if !(sketch.min.is_finite() && sketch.max.is_finite() && sketch.min <= sketch.max) {
+            return Err("invalid sketch totals");
+        }
+
+        Ok(sketch)
+    }
+}
+
+fn write_f64(buf: &mut Vec<u8>, value: f64) {
+    let bits = value.to_bits();
+
+    for i in 0..8 {
+        buf.push((bits >> (8 * i)) as u8);
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_bins(buf: &mut Vec<u8>, bins: &BTreeMap<i32, u64>) {
+    write_varint(buf, bins.len() as u64);
+
+    let mut previous = 0i64;
+    for (&index, &count) in bins {
+        let delta = index as i64 - previous;
+        write_varint(buf, ((delta << 1) ^ (delta >> 63)) as u64);
+        write_varint(buf, count);
+        previous = index as i64;
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        match self.buf.split_first() {
+            Some((&byte, rest)) => {
+                self.buf = rest;
+                Ok(byte)
+            }
+            None => Err("truncated sketch"),
+        }
+    }
+
+    fn f64(&mut self) -> Result<f64, &'static str> {
+        let mut bits = 0u64;
+
+        for i in 0..8 {
+            bits |= (try!(self.byte()) as u64) << (8 * i);
+        }
+
+        Ok(f64::from_bits(bits))
+    }
+
+    fn varint(&mut self) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        let mut shift = 0;
+
+        loop {
+            let byte = try!(self.byte());
+
+            if shift >= 64 {
+                return Err("invalid variable length integer");
+            }
+
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn bins(&mut self) -> Result<BTreeMap<i32, u64>, &'static str> {
+        let len = try!(self.varint());
+        let mut bins = BTreeMap::new();
+
+        let mut previous = 0i64;
+        for _ in 0..len {
+            let zigzag = try!(self.varint());
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            let index = try!(previous.checked_add(delta).ok_or("bin index out of range"));
+
+            if index < i32::min_value() as i64 || index > i32::max_value() as i64 {
+                return Err("bin index out of range");
+            }
+
+            bins.insert(index as i32, try!(self.varint()));
+            previous = index;
+        }
+
+        Ok(bins)
+    }
+}
+
+/// A histogram backed by a `DDSketch`.
+pub struct DDSketchHistogram {
+    sketch: Mutex<DDSketch>,
+}
+
+impl DDSketchHistogram {
+    /// Creates a new histogram with a relative accuracy of 1% and at most 2048 bins.
+    pub fn new() -> DDSketchHistogram {
+        DDSketchHistogram::with_accuracy(DEFAULT_RELATIVE_ACCURACY, DEFAULT_MAX_BINS)
+    }
+
+    /// Creates a new histogram with the given relative accuracy and maximum number of bins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the accuracy is not between 1e-6 inclusive and 1 exclusive, or if max_bins is
+    /// zero.
+    pub fn with_accuracy(relative_accuracy: f64, max_bins: usize) -> DDSketchHistogram {
+        DDSketchHistogram { sketch: Mutex::new(DDSketch::with_accuracy(relative_accuracy, max_bins)) }
+    }
+
+    /// Records a value. Infinite and NaN values are ignored.
+    pub fn observe(&self, value: f64) {
+        self.sketch.lock().unwrap().add(value);
+    }
+
+    /// Adds every value of a sketch, which may come from another histogram or process.
+    pub fn merge(&self, other: &DDSketch) -> Result<(), &'static str> {
+        self.sketch.lock().unwrap().merge(other)
+    }
+
+    pub fn snapshot(&self) -> DDSketch {
+        self.sketch.lock().unwrap().clone()
+    }
+}
+
+impl Metric for DDSketchHistogram {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Sketch(self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use metric::{Metric, MetricValue};
+    use super::*;
+
+    fn assert_relative(expected: f64, actual: f64, accuracy: f64) {
+        assert!((actual - expected).abs() <= expected.abs() * accuracy,
+                "{} != {}",
+                actual,
+                expected);
+    }
+
+    #[test]
+    fn empty() {
+        let s = DDSketch::new();
+
+        assert_eq!(0, s.count());
+        assert_eq!(0.0, s.percentile(50.0));
+        assert_eq!(0.0, s.min());
+    }
+
+    #[test]
+    fn relative_error() {
+        let mut s = DDSketch::new();
+
+        for v in 1..100001 {
+            s.add(v as f64);
+        }
+
+        assert_eq!(100000, s.count());
+        assert_eq!(1.0, s.percentile(0.0));
+        assert_eq!(100000.0, s.percentile(100.0));
+
+        for &p in [1.0, 25.0, 50.0, 90.0, 99.0, 99.9, 99.99].iter() {
+            assert_relative(p * 1000.0, s.percentile(p), 0.01);
+        }
+    }
+
+    #[test]
+    fn zero_and_negative() {
+        let mut s = DDSketch::new();
+
+        for v in -50..51 {
+            s.add(v as f64);
+        }
+
+        assert_eq!(101, s.count());
+        assert_eq!(0.0, s.sum());
+        assert_eq!(-50.0, s.percentile(0.0));
+        assert_eq!(0.0, s.percentile(50.0));
+        assert_relative(-25.0, s.percentile(25.0), 0.01);
+        assert_relative(25.0, s.percentile(75.0), 0.01);
+    }
+
+    #[test]
+    fn merge_is_exact() {
+        let mut a = DDSketch::new();
+        let mut b = DDSketch::new();
+        let mut all = DDSketch::new();
+
+        for v in 1..1001 {
+            a.add(v as f64);
+            b.add(v as f64 * 3.0);
+            all.add(v as f64);
+            all.add(v as f64 * 3.0);
+        }
+
+        a.merge(&b).unwrap();
+
+        assert_eq!(all.count(), a.count());
+        assert_eq!(all.encode(), a.encode());
+    }
+
+    #[test]
+    fn merge_different_accuracy() {
+        let mut a = DDSketch::new();
+        let b = DDSketch::with_accuracy(0.02, 2048);
+
+        assert!(a.merge(&b).is_err());
+    }
+
+    #[test]
+    fn collapses_lowest_bins() {
+        let mut s = DDSketch::with_accuracy(0.01, 100);
+
+        for v in 1..100001 {
+            s.add(v as f64);
+        }
+
+        assert_eq!(100, s.bins());
+        assert_eq!(100000, s.count());
+        assert_relative(99000.0, s.percentile(99.0), 0.01);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut s = DDSketch::new();
+        s.add(-7.5);
+        s.add(0.0);
+
+        for v in 1..1000 {
+            s.add(v as f64);
+        }
+
+        let bytes = s.encode();
+        let decoded = DDSketch::decode(&bytes).unwrap();
+
+        assert_eq!(s.count(), decoded.count());
+        assert_eq!(s.sum(), decoded.sum());
+        assert_eq!(s.min(), decoded.min());
+        assert_eq!(s.max(), decoded.max());
+        assert_eq!(s.relative_accuracy(), decoded.relative_accuracy());
+        assert_eq!(s.percentile(50.0), decoded.percentile(50.0));
+        assert_eq!(bytes, decoded.encode());
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let bytes = DDSketch::new().encode();
+
+        assert!(DDSketch::decode(&[]).is_err());
+        assert!(DDSketch::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(DDSketch::decode(&[0]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(DDSketch::decode(&trailing).is_err());
+    }
+
+    #[test]
+    fn decode_inconsistent() {
+        let mut s = DDSketch::with_accuracy(0.01, 2);
+        s.add(1.0);
+        s.add(10.0);
+        let bytes = s.encode();
+
+        // Offsets of the maximum number of bins, of the sum, and of the minimum and maximum.
+        let (max_bins, sum, min, max) = (9, 10, 18, 26);
+        assert!(DDSketch::decode(&bytes).is_ok());
+
+        let mut bins = bytes.clone();
+        bins[max_bins] = 1;
+        assert_eq!(Err("more sketch bins than allowed"), DDSketch::decode(&bins).map(|_| ()));
+
+        let mut nan = bytes[..sum].to_vec();
+        write_f64(&mut nan, f64::NAN);
+        nan.extend_from_slice(&bytes[min..]);
+        assert_eq!(Err("invalid sketch totals"), DDSketch::decode(&nan).map(|_| ()));
+
+        let mut infinite = bytes[..max].to_vec();
+        write_f64(&mut infinite, f64::INFINITY);
+        infinite.extend_from_slice(&bytes[max + 8..]);
+        assert_eq!(Err("invalid sketch totals"), DDSketch::decode(&infinite).map(|_| ()));
+
+        let mut swapped = bytes[..min].to_vec();
+        swapped.extend_from_slice(&bytes[max..max + 8]);
+        swapped.extend_from_slice(&bytes[min..max]);
+        swapped.extend_from_slice(&bytes[max + 8..]);
+        assert_eq!(Err("invalid sketch totals"), DDSketch::decode(&swapped).map(|_| ()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn accuracy_too_fine() {
+        DDSketch::with_accuracy(1e-10, 2048);
+    }
+
+    #[test]
+    fn finest_accuracy_extremes() {
+        let mut s = DDSketch::with_accuracy(MIN_RELATIVE_ACCURACY, 2048);
+        s.add(5e-324);
+        s.add(1.0);
+        s.add(f64::MAX);
+
+        // Every finite value keeps a bin of its own.
+        assert_eq!(3, s.bins());
+        assert_eq!(s.encode(), DDSketch::decode(&s.encode()).unwrap().encode());
+    }
+
+    #[test]
+    fn decode_overflow() {
+        let bytes = DDSketch::new().encode();
+        // Everything up to the zero count, followed by crafted counts.
+        let header = &bytes[..bytes.len() - 3];
+
+        let mut count = header.to_vec();
+        write_varint(&mut count, u64::max_value());
+        write_varint(&mut count, 1);
+        write_varint(&mut count, 0);
+        write_varint(&mut count, 1);
+        write_varint(&mut count, 0);
+        assert_eq!(Err("sketch count overflow"), DDSketch::decode(&count).map(|_| ()));
+
+        let mut index = header.to_vec();
+        write_varint(&mut index, 0);
+        write_varint(&mut index, 2);
+        write_varint(&mut index, 2 * i32::max_value() as u64);
+        write_varint(&mut index, 1);
+        write_varint(&mut index, u64::max_value() - 1);
+        write_varint(&mut index, 1);
+        write_varint(&mut index, 0);
+        assert_eq!(Err("bin index out of range"), DDSketch::decode(&index).map(|_| ()));
+    }
+
+    #[test]
+    fn export() {
+        let h = DDSketchHistogram::new();
+        h.observe(2.5);
+
+        match h.export_metric() {
+            MetricValue::Sketch(s) => assert_eq!(1, s.count()),
+            _ => panic!("expected a sketch"),
+        }
+    }
+}
@@ -1,8 +1,10 @@
-use std::sync::atomic::AtomicBool;
-use std::sync::Mutex;
+use std::f64;
+use std::time::Duration;
 
 use syncbox::atomic::{AtomicI64, Ordering};
 
+use striped::StripedCounter;
+
 /// An exponentially-weighted moving average.
 ///
 /// \see http://www.teamquest.com/pdfs/whitepaper/ldavg1.pdf UNIX Load Average Part 1: How It Works
@@ -10,11 +12,13 @@ use syncbox::atomic::{AtomicI64, Ordering};
 /// \see http://en.wikipedia.org/wiki/Moving_average#Exponential_moving_average EMA
 pub struct EWMA {
     // This tracks uncounted events.
-    uncounted: AtomicI64,
+    uncounted: StripedCounter,
     alpha: f64,
     interval: f64,
-    rate: Mutex<f64>,
-    initialized: AtomicBool,
+    // Bits of the f64 rate per nanosecond, so that it can be read and updated without a lock. It
+    // holds NaN until the first tick, which is decided by the same compare-and-swap which updates
+    // the rate.
+    rate: AtomicI64,
 }
 
 pub struct EWMASnapshot {
@@ -28,14 +32,27 @@ impl EWMASnapshot {
 }
 
 impl EWMA {
-    /// Creates a new EWMA with a specific smoothing constant.
+    /// Creates a new EWMA with a specific smoothing constant, which expects to be ticked every 5
+    /// seconds.
     pub fn from_alpha(alpha: f64) -> EWMA {
+        EWMA::from_alpha_and_interval(alpha, Duration::from_secs(5))
+    }
+
+    /// Creates a new EWMA with a specific smoothing constant, which expects to be ticked every
+    /// `tick_interval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if tick_interval is zero.
+    pub fn from_alpha_and_interval(alpha: f64, tick_interval: Duration) -> EWMA {
+        let interval = nanos(tick_interval);
+        assert!(interval > 0.0);
+
         EWMA {
-            uncounted: AtomicI64::new(0),
+            uncounted: StripedCounter::new(),
             alpha: alpha,
-            interval: 5e9,
-            rate: Mutex::new(0f64),
-            initialized: AtomicBool::new(false),
+            interval: interval,
+            rate: AtomicI64::new(f64::NAN.to_bits() as i64),
          }
     }
 
@@ -51,6 +68,20 @@ impl EWMA {
         EWMA::from_alpha(1.0f64 - i.exp())
     }
 
+    /// Creates a new EWMA averaging over the given window, which expects to be ticked every
+    /// `tick_interval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if window or tick_interval is zero.
+    pub fn with_window(window: Duration, tick_interval: Duration) -> EWMA {
+        let window = nanos(window);
+        assert!(window > 0.0);
+
+        let alpha = 1.0f64 - (-nanos(tick_interval) / window).exp();
+        EWMA::from_alpha_and_interval(alpha, tick_interval)
+    }
+
     /// Creates a new EWMA which is equivalent to the UNIX one minute load average and which
     /// expects to be ticked every 5 seconds.
     pub fn m01rate() -> EWMA {
@@ -70,9 +101,13 @@ impl EWMA {
     }
 
     pub fn rate(&self) -> f64 {
-        let rate = self.rate.lock().unwrap();
+        let rate = f64::from_bits(self.rate.load(Ordering::SeqCst) as u64);
+
+        if rate.is_nan() {
+            return 0.0;
+        }
 
-        *rate * (1e9 as f64)
+        rate * (1e9 as f64)
     }
 
     pub fn snapshot(&self) -> EWMASnapshot {
@@ -81,24 +116,48 @@ impl EWMA {
 
     /// Mark the passage of time and decay the current rate accordingly.
     pub fn tick(&self) {
-        let count = self.uncounted.swap(0, Ordering::SeqCst);
-        let instant_rate = (count as f64) / self.interval;
+        self.tick_n(1);
+    }
 
-        let mut rate = self.rate.lock().unwrap();
+    /// Mark the passage of the given number of ticks at once, as if `tick` were called that many
+    /// times. The uncounted events fall in the first tick, and the others decay the rate in
+    /// closed form.
+    pub fn tick_n(&self, ticks: u64) {
+        if ticks == 0 {
+            return;
+        }
 
-        if self.initialized.swap(true, Ordering::Relaxed) {
-            *rate += self.alpha * (instant_rate - *rate);
-        } else {
-            *rate = instant_rate;
+        let count = self.uncounted.sum_and_reset();
+        let instant_rate = (count as f64) / self.interval;
+        let decay = (1.0 - self.alpha).powf((ticks - 1) as f64);
+
+        loop {
+            let old = self.rate.load(Ordering::SeqCst);
+            let rate = f64::from_bits(old as u64);
+
+            let rate = if rate.is_nan() {
+                instant_rate
+            } else {
+                rate + self.alpha * (instant_rate - rate)
+            };
+            let rate = rate * decay;
+
+            if self.rate.compare_and_swap(old, rate.to_bits() as i64, Ordering::SeqCst) == old {
+                break;
+            }
         }
     }
 
     /// Update the moving average with a new value.
     pub fn update(&self, value: i64) {
-        self.uncounted.fetch_add(value, Ordering::SeqCst);
+        self.uncounted.add(value);
     }
 }
 
+fn nanos(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e9 + duration.subsec_nanos() as f64
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -285,6 +344,129 @@ mod test {
         assert_eq!(within(&mut e, 0.2207276647028646247028654470286553f64), true);
     }
 
+    #[test]
+    fn window() {
+        use std::time::Duration;
+
+        let e = EWMA::with_window(Duration::from_secs(10), Duration::from_secs(1));
+        e.update(10);
+        e.tick();
+
+        assert!((e.rate() - 10.0).abs() < 1e-9);
+
+        // After one window, the rate has decayed by a factor of e.
+        for _ in 0..10 {
+            e.tick();
+        }
+        assert!((e.rate() - 10.0 / 1f64.exp()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn window_matches_minutes() {
+        use std::time::Duration;
+
+        let mut e = EWMA::with_window(Duration::from_secs(60), Duration::from_secs(5));
+        e.update(3);
+        e.tick();
+
+        assert_eq!(0.6, e.rate());
+        assert!(within(&mut e, 0.22072766470286553));
+    }
+
+    #[test]
+    fn concurrent_ticks() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let e = Arc::new(EWMA::new(1f64));
+        e.update(3);
+        e.tick();
+
+        let threads: Vec<_> = (0..4)
+                                  .map(|_| {
+                                      let e = e.clone();
+                                      thread::spawn(move || {
+                                          for _ in 0..3 {
+                                              e.tick();
+                                          }
+                                      })
+                                  })
+                                  .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        // Every tick is applied exactly once, as if they were sequential.
+        let mut sequential = EWMA::new(1f64);
+        sequential.update(3);
+        sequential.tick();
+        elapse_minute(&mut sequential);
+
+        assert_eq!(sequential.rate(), e.rate());
+    }
+
+    #[test]
+    fn concurrent_first_ticks() {
+        use std::sync::{Arc, Barrier};
+        use std::thread;
+
+        for _ in 0..1000 {
+            let e = Arc::new(EWMA::new(1f64));
+            let barrier = Arc::new(Barrier::new(2));
+            e.update(12);
+
+            let threads: Vec<_> = (0..2)
+                                      .map(|_| {
+                                          let e = e.clone();
+                                          let barrier = barrier.clone();
+                                          thread::spawn(move || {
+                                              barrier.wait();
+                                              e.tick();
+                                          })
+                                      })
+                                      .collect();
+
+            for t in threads {
+                t.join().unwrap();
+            }
+
+            // However the events are split between both ticks, neither tick may discard the
+            // other one's result. The lowest sequential outcome leaves alpha of the events.
+            let alpha = 1.0 - (-5.0f64 / 60.0).exp();
+            assert!(e.rate() >= alpha * 12.0 / 5.0 - 1e-9, "{}", e.rate());
+        }
+    }
+
+    #[test]
+    fn many_ticks_at_once() {
+        let mut sequential = EWMA::new(1f64);
+        sequential.update(3);
+        sequential.tick();
+        sequential.update(6);
+        elapse_minute(&mut sequential);
+
+        let e = EWMA::new(1f64);
+        e.update(3);
+        e.tick_n(1);
+        e.update(6);
+        e.tick_n(12);
+
+        assert!((sequential.rate() - e.rate()).abs() < 1e-12, "{}", e.rate());
+
+        e.tick_n(0);
+        assert!((sequential.rate() - e.rate()).abs() < 1e-12, "{}", e.rate());
+    }
+
+    #[test]
+    fn zero_before_first_tick() {
+        let e = EWMA::m01rate();
+        e.update(5);
+
+        assert_eq!(0.0, e.rate());
+        assert_eq!(0.0, e.snapshot().rate());
+    }
+
     #[test]
     fn send() {
         fn checker<T: Send>(_ :T) {}
@@ -0,0 +1,437 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::f64;
+use std::sync::Mutex;
+
+use metric::{Metric, MetricValue};
+use snapshot::PERCENTILES;
+use summary::SummarySnapshot;
+
+const MAX_SCALE: i32 = 20;
+const MIN_SCALE: i32 = -10;
+const DEFAULT_MAX_BUCKETS: usize = 160;
+
+/// The buckets of an exponential histogram.
+///
+/// Bucket `i` of the positive range counts the values in `(base^i, base^(i+1)]`, where
+/// `base = 2^(2^-scale)`; the negative range mirrors it. Only buckets which have been hit are
+/// stored. Whenever more than `max_buckets` buckets are in use, the scale is lowered by one,
+/// which squares the base and merges every pair of neighbouring buckets.
+#[derive(Clone, Debug)]
+pub struct ExponentialSnapshot {
+    scale: i32,
+    max_buckets: usize,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl ExponentialSnapshot {
+    fn new(max_buckets: usize) -> ExponentialSnapshot {
+        ExponentialSnapshot {
+            scale: MAX_SCALE,
+            max_buckets: max_buckets,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+
+    /// Returns the smallest value, or zero if the snapshot is empty.
+    pub fn min(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.min }
+    }
+
+    /// Returns the largest value, or zero if the snapshot is empty.
+    pub fn max(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.max }
+    }
+
+    /// Returns the index and count of every bucket holding positive values, in ascending order.
+    pub fn positive_buckets(&self) -> Vec<(i32, u64)> {
+        self.positive.iter().map(|(&i, &c)| (i, c)).collect()
+    }
+
+    /// Returns the index and count of every bucket holding negative values, in ascending order of
+    /// their index, that is of the absolute value.
+    pub fn negative_buckets(&self) -> Vec<(i32, u64)> {
+        self.negative.iter().map(|(&i, &c)| (i, c)).collect()
+    }
+
+    /// Returns the lower bound of the absolute values of the bucket with the given index.
+    ///
+    /// At the lowest scales, the upper buckets reach beyond the largest finite value, and their
+    /// bounds are clamped to it.
+    pub fn lower_bound(&self, index: i32) -> f64 {
+        (index as f64 * (-self.scale as f64).exp2()).exp2().min(f64::MAX)
+    }
+
+    fn index(&self, value: f64) -> i32 {
+        (value.log2() * (self.scale as f64).exp2()).ceil() as i32 - 1
+    }
+
+    fn record(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        if value == 0.0 {
+            self.zero_count += 1;
+            return;
+        }
+
+        let index = self.index(value.abs());
+        let buckets = if value > 0.0 { &mut self.positive } else { &mut self.negative };
+        *buckets.entry(index).or_insert(0) += 1;
+
+        self.fit();
+    }
+
+    /// Adds every value of another snapshot, lowering the resolution to the coarsest of both.
+    pub fn merge(&mut self, other: &ExponentialSnapshot) {
+        let mut other = other.clone();
+
+        while self.scale > other.scale {
+            self.downscale();
+        }
+        while other.scale > self.scale {
+            other.downscale();
+        }
+
+        for (&i, &c) in &other.positive {
+            *self.positive.entry(i).or_insert(0) += c;
+        }
+        for (&i, &c) in &other.negative {
+            *self.negative.entry(i).or_insert(0) += c;
+        }
+
+        self.zero_count += other.zero_count;
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+
+        self.fit();
+    }
+
+    fn fit(&mut self) {
+        while self.positive.len() + self.negative.len() > self.max_buckets && self.scale > MIN_SCALE {
+            self.downscale();
+        }
+    }
+
+    fn downscale(&mut self) {
+        fn halve(buckets: &BTreeMap<i32, u64>) -> BTreeMap<i32, u64> {
+            let mut halved = BTreeMap::new();
+            for (&i, &c) in buckets {
+                // An arithmetic shift rounds towards negative infinity, as needed.
+                *halved.entry(i >> 1).or_insert(0) += c;
+            }
+            halved
+        }
+
+        self.positive = halve(&self.positive);
+        self.negative = halve(&self.negative);
+        self.scale -= 1;
+    }
+
+    // The point of the bucket with the lowest relative error to any value within it, the harmonic
+    // mean of its bounds. It's computed from their reciprocals, which stay finite for the widest
+    // buckets.
+    fn representative(&self, index: i32) -> f64 {
+        let lower = self.lower_bound(index);
+        let upper = self.lower_bound(index + 1);
+
+        2.0 / (1.0 / lower + 1.0 / upper)
+    }
+
+    /// Returns the value at the given percentile, from 0 to 100.
+    ///
+    /// Every bucket takes part in the computation, so the result is off by at most the relative
+    /// width of a bucket, `(base - 1) / (base + 1)`. Returns zero if the snapshot is empty.
+    pub fn percentile(&self, percentile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        let rank = cmp::max(1, (percentile / 100.0 * self.count as f64).ceil() as u64);
+
+        // The extremes are known exactly.
+        if rank == 1 {
+            return self.min;
+        }
+        if rank >= self.count {
+            return self.max;
+        }
+
+        let mut seen = 0;
+
+        let negative = self.negative.iter().rev().map(|(&i, &c)| (-self.representative(i), c));
+        let zero = Some((0.0, self.zero_count)).into_iter();
+        let positive = self.positive.iter().map(|(&i, &c)| (self.representative(i), c));
+
+        for (value, count) in negative.chain(zero).chain(positive) {
+            seen += count;
+
+            if seen >= rank {
+                return value.max(self.min).min(self.max);
+            }
+        }
+
+        self.max
+    }
+
+    /// Converts the buckets into a summary of the values at the given quantiles, from 0 to 1, for
+    /// reporters which only understand summaries.
+    ///
+    /// Each quantile is computed from every bucket, as by `percentile`, so the conversion adds no
+    /// error beyond the resolution of the buckets.
+    pub fn to_summary(&self, quantiles: &[f64]) -> SummarySnapshot {
+        SummarySnapshot {
+            quantiles: quantiles.iter().map(|&q| (q, self.percentile(q * 100.0))).collect(),
+            count: self.count,
+            sum: self.sum,
+        }
+    }
+
+    /// Converts the buckets into a summary of the percentiles reported for every histogram.
+    pub fn summary(&self) -> SummarySnapshot {
+        let quantiles: Vec<f64> = PERCENTILES.iter().map(|&(p, _)| p / 100.0).collect();
+        self.to_summary(&quantiles)
+    }
+}
+
+/// A histogram with exponentially sized buckets which adapts its resolution to the values.
+///
+/// This avoids choosing bucket bounds up front: the histogram starts at the highest resolution
+/// and coarsens it whenever the recorded values span more buckets than allowed.
+pub struct ExponentialHistogram {
+    buckets: Mutex<ExponentialSnapshot>,
+}
+
+impl ExponentialHistogram {
+    /// Creates a new histogram using at most 160 buckets.
+    pub fn new() -> ExponentialHistogram {
+        ExponentialHistogram::with_max_buckets(DEFAULT_MAX_BUCKETS)
+    }
+
+    /// Creates a new histogram using at most `max_buckets` buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if max_buckets < 2.
+    pub fn with_max_buckets(max_buckets: usize) -> ExponentialHistogram {
+        assert!(max_buckets >= 2);
+
+        ExponentialHistogram { buckets: Mutex::new(ExponentialSnapshot::new(max_buckets)) }
+    }
+
+    /// Records a value. Infinite and NaN values are ignored.
+    pub fn observe(&self, value: f64) {
+        self.buckets.lock().unwrap().record(value);
+    }
+
+    /// Adds every value of a snapshot, which may come from another histogram or process.
+    pub fn merge(&self, other: &ExponentialSnapshot) {
+        self.buckets.lock().unwrap().merge(other);
+    }
+
+    pub fn snapshot(&self) -> ExponentialSnapshot {
+        self.buckets.lock().unwrap().clone()
+    }
+}
+
+impl Metric for ExponentialHistogram {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Exponential(self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use metric::{Metric, MetricValue};
+    use super::*;
+
+    #[test]
+    fn bucket_bounds() {
+        let h = ExponentialHistogram::new();
+        h.observe(1.0);
+        h.observe(2.0);
+        h.observe(4.0);
+
+        let s = h.snapshot();
+        let scale = s.scale();
+
+        // Powers of two are the upper bounds of their buckets.
+        assert_eq!(vec![(-1, 1), ((1 << scale) - 1, 1), ((2 << scale) - 1, 1)],
+                   s.positive_buckets());
+    }
+
+    #[test]
+    fn downscales_to_fit() {
+        let h = ExponentialHistogram::with_max_buckets(4);
+
+        for &v in [1.0, 2.0, 4.0, 8.0, 16.0, 1000.0].iter() {
+            h.observe(v);
+        }
+
+        let s = h.snapshot();
+        assert!(s.positive_buckets().len() <= 4);
+        assert!(s.scale() < MAX_SCALE);
+        assert_eq!(6, s.positive_buckets().iter().fold(0, |sum, &(_, c)| sum + c));
+    }
+
+    #[test]
+    fn zero_and_negative() {
+        let h = ExponentialHistogram::new();
+        h.observe(0.0);
+        h.observe(-3.0);
+        h.observe(5.0);
+        h.observe(::std::f64::NAN);
+
+        let s = h.snapshot();
+        assert_eq!(3, s.count());
+        assert_eq!(1, s.zero_count());
+        assert_eq!(1, s.negative_buckets().len());
+        assert_eq!(2.0, s.sum());
+        assert_eq!(-3.0, s.min());
+        assert_eq!(5.0, s.max());
+        assert_eq!(-3.0, s.percentile(0.0));
+        assert_eq!(0.0, s.percentile(50.0));
+        assert_eq!(5.0, s.percentile(100.0));
+    }
+
+    #[test]
+    fn percentiles_within_relative_error() {
+        let h = ExponentialHistogram::with_max_buckets(20);
+
+        for v in 1..10001 {
+            h.observe(v as f64);
+        }
+
+        let s = h.snapshot();
+        let base = (-s.scale() as f64).exp2().exp2();
+        let error = (base - 1.0) / (base + 1.0);
+
+        for &p in [1.0, 25.0, 50.0, 90.0, 99.0, 99.9].iter() {
+            let expected = p * 100.0;
+            let actual = s.percentile(p);
+
+            assert!((actual - expected).abs() <= expected * error,
+                    "p{}: {} != {}",
+                    p,
+                    actual,
+                    expected);
+        }
+    }
+
+    #[test]
+    fn minimum_scale() {
+        let h = ExponentialHistogram::with_max_buckets(2);
+
+        for &v in [5e-324, 0.5, 1e300, 1e300, 1e300, 1e308].iter() {
+            h.observe(v);
+        }
+
+        let s = h.snapshot();
+        assert_eq!(MIN_SCALE, s.scale());
+        assert_eq!(f64::MAX, s.lower_bound(1));
+
+        for &p in [10.0, 30.0, 50.0, 70.0, 90.0].iter() {
+            let value = s.percentile(p);
+            assert!(value.is_finite() && value >= s.min() && value <= s.max(), "p{}: {}", p, value);
+        }
+
+        // The largest values have their own bucket, rather than falling back to the minimum.
+        assert!(s.percentile(50.0) > 1.0);
+    }
+
+    #[test]
+    fn merge() {
+        let a = ExponentialHistogram::new();
+        let b = ExponentialHistogram::with_max_buckets(4);
+
+        for v in 1..100 {
+            a.observe(v as f64);
+            b.observe(v as f64 * 1000.0);
+        }
+
+        a.merge(&b.snapshot());
+
+        let s = a.snapshot();
+        assert_eq!(198, s.count());
+        assert!(s.scale() <= b.snapshot().scale());
+        assert_eq!(99000.0, s.percentile(100.0));
+        assert_eq!(1.0, s.percentile(0.0));
+    }
+
+    #[test]
+    fn empty() {
+        let s = ExponentialHistogram::new().snapshot();
+
+        assert_eq!(0.0, s.min());
+        assert_eq!(0.0, s.max());
+        assert_eq!(0.0, s.percentile(50.0));
+    }
+
+    #[test]
+    fn to_summary() {
+        let h = ExponentialHistogram::new();
+
+        for v in 1..1001 {
+            h.observe(v as f64);
+        }
+
+        let s = h.snapshot();
+        let summary = s.to_summary(&[0.5, 0.99, 1.0]);
+
+        assert_eq!(1000, summary.count);
+        assert_eq!(500500.0, summary.sum);
+        assert_eq!(vec![(0.5, s.percentile(50.0)), (0.99, s.percentile(99.0)), (1.0, 1000.0)],
+                   summary.quantiles);
+
+        let quantiles: Vec<f64> = s.summary().quantiles.iter().map(|&(q, _)| q).collect();
+        assert_eq!(PERCENTILES.len(), quantiles.len());
+        assert_eq!(0.5, quantiles[0]);
+    }
+
+    #[test]
+    fn export() {
+        let h = ExponentialHistogram::new();
+        h.observe(1.5);
+
+        match h.export_metric() {
+            MetricValue::Exponential(s) => assert_eq!(1, s.count()),
+            _ => panic!("expected an exponential histogram"),
+        }
+    }
+}
@@ -1,34 +1,317 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use counter::AtomicCounter;
+use handle::CounterHandle;
+use meter::{self, Clock, Meter, SystemClock};
 use metric::{Metric, MetricValue};
+use striped::StripedCounter;
+
+/// The value of a gauge, kept in the type it was recorded with so that no precision is lost on
+/// the way to a reporter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GaugeValue {
+    Signed(i64),
+    Unsigned(u64),
+    Float(f64),
+    Bool(bool)
+}
+
+impl fmt::Display for GaugeValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GaugeValue::Signed(x) => write!(f, "{}", x),
+            GaugeValue::Unsigned(x) => write!(f, "{}", x),
+            GaugeValue::Float(x) => write!(f, "{}", x),
+            // Reporters such as Carbon only understand numbers.
+            GaugeValue::Bool(x) => write!(f, "{}", x as u8),
+        }
+    }
+}
+
+impl From<i64> for GaugeValue {
+    fn from(value: i64) -> GaugeValue {
+        GaugeValue::Signed(value)
+    }
+}
+
+impl From<u64> for GaugeValue {
+    fn from(value: u64) -> GaugeValue {
+        GaugeValue::Unsigned(value)
+    }
+}
+
+impl From<f64> for GaugeValue {
+    fn from(value: f64) -> GaugeValue {
+        GaugeValue::Float(value)
+    }
+}
+
+impl From<bool> for GaugeValue {
+    fn from(value: bool) -> GaugeValue {
+        GaugeValue::Bool(value)
+    }
+}
 
 #[derive(Copy, Clone, Debug)]
-pub struct StdGauge {
-    pub value: f64
+pub struct StdGauge<T = f64> {
+    pub value: T
 }
 
-pub trait Gauge {
-    fn update(&mut self, value: f64);
+pub trait Gauge<T = f64> {
+    fn update(&mut self, value: T);
 
     fn snapshot(self) -> Self;
 }
 
-impl Gauge for StdGauge {
-    fn update(&mut self, value: f64) {
+impl<T: Copy> Gauge<T> for StdGauge<T> {
+    fn update(&mut self, value: T) {
         self.value = value
     }
 
-    fn snapshot(self) -> StdGauge {
+    fn snapshot(self) -> StdGauge<T> {
         StdGauge { value: self.value }
     }
 }
 
-impl Metric for StdGauge {
+impl<T> Metric for StdGauge<T>
+    where T: Into<GaugeValue> + Copy + Send + Sync
+{
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Gauge(self.value.into())
+    }
+}
+
+/// A gauge whose value is computed by a callback each time it is exported.
+///
+/// This suits values which already live elsewhere, such as queue lengths or pool sizes, so they
+/// don't have to be pushed into a gauge on every change.
+pub struct CallbackGauge<F> {
+    callback: F
+}
+
+impl<F, T> CallbackGauge<F>
+    where F: Fn() -> T
+{
+    pub fn new(callback: F) -> CallbackGauge<F> {
+        CallbackGauge { callback: callback }
+    }
+
+    /// Invokes the callback and returns its current value.
+    pub fn value(&self) -> T {
+        (self.callback)()
+    }
+}
+
+impl<F, T> Metric for CallbackGauge<F>
+    where F: Fn() -> T + Send + Sync,
+          T: Into<GaugeValue>
+{
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Gauge(self.value().into())
+    }
+}
+
+/// A callback gauge which reuses its last computed value until it expires.
+///
+/// Useful for values which are expensive to compute, such as a directory size, when several
+/// reporters poll the same registry.
+pub struct CachedGauge<T, F, C: Clock = SystemClock> {
+    callback: F,
+    timeout: i64,
+    clock: C,
+    // The last computed value together with the time it was computed at.
+    cached: Mutex<Option<(i64, T)>>,
+}
+
+impl<T, F> CachedGauge<T, F, SystemClock>
+    where T: Copy,
+          F: Fn() -> T
+{
+    /// Creates a new gauge whose value is recomputed at most once per `timeout`.
+    pub fn new(callback: F, timeout: Duration) -> CachedGauge<T, F> {
+        CachedGauge::with_clock(callback, timeout, SystemClock)
+    }
+}
+
+impl<T, F, C> CachedGauge<T, F, C>
+    where T: Copy,
+          F: Fn() -> T,
+          C: Clock
+{
+    /// Creates a new gauge whose value is recomputed at most once per `timeout`, as measured by
+    /// the given clock.
+    pub fn with_clock(callback: F, timeout: Duration, clock: C) -> CachedGauge<T, F, C> {
+        CachedGauge {
+            callback: callback,
+            timeout: meter::to_nanos(timeout),
+            clock: clock,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the cached value, invoking the callback first if it has expired.
+    pub fn value(&self) -> T {
+        let now = self.clock.now();
+
+        // The lock is held while computing, so concurrent readers wait for a single computation
+        // instead of each running the callback.
+        let mut cached = self.cached.lock().unwrap();
+
+        match *cached {
+            Some((timestamp, value)) if now - timestamp < self.timeout => value,
+            _ => {
+                let value = (self.callback)();
+                *cached = Some((now, value));
+                value
+            }
+        }
+    }
+
+    /// Forces the next call to `value` to invoke the callback.
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
+impl<T, F, C> Metric for CachedGauge<T, F, C>
+    where T: Into<GaugeValue> + Copy + Send,
+          F: Fn() -> T + Send + Sync,
+          C: Clock
+{
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Gauge(self.value().into())
+    }
+}
+
+/// A source for the numerator or the denominator of a `RatioGauge`.
+pub trait RatioSource: Send + Sync {
+    fn ratio_value(&self) -> f64;
+}
+
+impl<F> RatioSource for F
+    where F: Fn() -> f64 + Send + Sync
+{
+    fn ratio_value(&self) -> f64 {
+        self()
+    }
+}
+
+impl RatioSource for AtomicCounter {
+    fn ratio_value(&self) -> f64 {
+        self.value() as f64
+    }
+}
+
+impl RatioSource for StripedCounter {
+    fn ratio_value(&self) -> f64 {
+        self.sum() as f64
+    }
+}
+
+impl RatioSource for CounterHandle {
+    fn ratio_value(&self) -> f64 {
+        self.snapshot().value as f64
+    }
+}
+
+/// One of the rates of a meter, used as a `RatioSource`.
+pub struct MeterRate<M> {
+    meter: M,
+    rate: fn(&M) -> f64,
+}
+
+impl<M: Meter> MeterRate<M> {
+    pub fn mean_rate(meter: M) -> MeterRate<M> {
+        MeterRate { meter: meter, rate: M::mean_rate }
+    }
+
+    pub fn m01rate(meter: M) -> MeterRate<M> {
+        MeterRate { meter: meter, rate: M::m01rate }
+    }
+
+    pub fn m05rate(meter: M) -> MeterRate<M> {
+        MeterRate { meter: meter, rate: M::m05rate }
+    }
+
+    pub fn m15rate(meter: M) -> MeterRate<M> {
+        MeterRate { meter: meter, rate: M::m15rate }
+    }
+}
+
+impl<M: Meter> RatioSource for MeterRate<M> {
+    fn ratio_value(&self) -> f64 {
+        (self.rate)(&self.meter)
+    }
+}
+
+/// A gauge which reports the ratio of two sources, such as cache hits over lookups.
+///
+/// Both sources are read at export time. A ratio is undefined when the denominator is zero or
+/// either side is NaN; the gauge then reports a fallback value instead of `inf` or `NaN`.
+pub struct RatioGauge<N, D> {
+    numerator: N,
+    denominator: D,
+    fallback: f64,
+}
+
+impl<N: RatioSource, D: RatioSource> RatioGauge<N, D> {
+    /// Creates a new ratio gauge which reports zero while the ratio is undefined.
+    pub fn new(numerator: N, denominator: D) -> RatioGauge<N, D> {
+        RatioGauge::with_fallback(numerator, denominator, 0.0)
+    }
+
+    /// Creates a new ratio gauge which reports `fallback` while the ratio is undefined.
+    pub fn with_fallback(numerator: N, denominator: D, fallback: f64) -> RatioGauge<N, D> {
+        RatioGauge {
+            numerator: numerator,
+            denominator: denominator,
+            fallback: fallback,
+        }
+    }
+
+    /// Returns the current ratio, or `None` if it is undefined.
+    pub fn ratio(&self) -> Option<f64> {
+        let numerator = self.numerator.ratio_value();
+        let denominator = self.denominator.ratio_value();
+
+        if numerator.is_nan() || denominator.is_nan() || denominator == 0.0 {
+            return None;
+        }
+
+        let ratio = numerator / denominator;
+
+        if ratio.is_finite() {
+            Some(ratio)
+        } else {
+            None
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        self.ratio().unwrap_or(self.fallback)
+    }
+}
+
+impl<N: RatioSource, D: RatioSource> Metric for RatioGauge<N, D> {
     fn export_metric(&self) -> MetricValue {
-        MetricValue::Gauge(self.snapshot())
+        MetricValue::Gauge(GaugeValue::Float(self.value()))
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use std::time::Duration;
+
+    use counter::AtomicCounter;
+    use handle::MeterHandle;
+    use meter::{ManualClock, Meter, NANOS_PER_SEC, StdMeter};
+    use metric::{Metric, MetricValue};
+    use registry::{Registry, StdRegistry};
     use super::*;
 
     #[test]
@@ -41,4 +324,206 @@ mod test {
         assert_eq!(g.value, 0f64);
         assert_eq!(g_snapshot.value, 10f64);
     }
+
+    #[test]
+    fn export_keeps_type() {
+        let signed = StdGauge { value: -3i64 };
+        let unsigned = StdGauge { value: u64::max_value() };
+        let float = StdGauge { value: 0.5f64 };
+        let boolean = StdGauge { value: true };
+
+        match signed.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Signed(-3), x),
+            _ => panic!("expected a gauge"),
+        }
+        match unsigned.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Unsigned(u64::max_value()), x),
+            _ => panic!("expected a gauge"),
+        }
+        match float.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Float(0.5), x),
+            _ => panic!("expected a gauge"),
+        }
+        match boolean.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Bool(true), x),
+            _ => panic!("expected a gauge"),
+        }
+    }
+
+    #[test]
+    fn format_is_lossless() {
+        assert_eq!("18446744073709551615", GaugeValue::Unsigned(u64::max_value()).to_string());
+        assert_eq!("-9223372036854775808", GaugeValue::Signed(i64::min_value()).to_string());
+        assert_eq!("9007199254740993", GaugeValue::Signed(9007199254740993).to_string());
+        assert_eq!("0.1", GaugeValue::Float(0.1).to_string());
+        assert_eq!("1", GaugeValue::Bool(true).to_string());
+        assert_eq!("0", GaugeValue::Bool(false).to_string());
+    }
+
+    #[test]
+    fn callback_evaluated_on_export() {
+        let queue = Arc::new(AtomicUsize::new(0));
+        let q = queue.clone();
+        let g = CallbackGauge::new(move || q.load(Ordering::SeqCst) as u64);
+
+        queue.store(3, Ordering::SeqCst);
+        match g.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Unsigned(3), x),
+            _ => panic!("expected a gauge"),
+        }
+
+        queue.store(5, Ordering::SeqCst);
+        match g.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Unsigned(5), x),
+            _ => panic!("expected a gauge"),
+        }
+    }
+
+    #[test]
+    fn callback_in_registry() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = calls.clone();
+
+        let mut r = StdRegistry::new();
+        r.insert("calls",
+                 CallbackGauge::new(move || c.fetch_add(1, Ordering::SeqCst) as i64 + 1));
+
+        r.get("calls").export_metric();
+        match r.get("calls").export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Signed(2), x),
+            _ => panic!("expected a gauge"),
+        }
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cached_until_expiry() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = calls.clone();
+        let g = CachedGauge::with_clock(move || c.fetch_add(1, Ordering::SeqCst) as u64,
+                                  Duration::from_secs(10),
+                                  ManualClock::new());
+
+        assert_eq!(0, g.value());
+        assert_eq!(0, g.value());
+
+        g.clock.set(9 * NANOS_PER_SEC);
+        assert_eq!(0, g.value());
+
+        g.clock.set(10 * NANOS_PER_SEC);
+        assert_eq!(1, g.value());
+        assert_eq!(1, g.value());
+
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cached_invalidate() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = calls.clone();
+        let g = CachedGauge::with_clock(move || c.fetch_add(1, Ordering::SeqCst) as u64,
+                                  Duration::from_secs(10),
+                                  ManualClock::new());
+
+        assert_eq!(0, g.value());
+
+        g.invalidate();
+
+        assert_eq!(1, g.value());
+    }
+
+    #[test]
+    fn cached_export() {
+        let g = CachedGauge::new(|| 7i64, Duration::from_secs(60));
+
+        match g.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Signed(7), x),
+            _ => panic!("expected a gauge"),
+        }
+    }
+
+    #[test]
+    fn ratio_of_counters() {
+        let hits = Arc::new(AtomicCounter::new());
+        let lookups = Arc::new(AtomicCounter::new());
+        let (h, l) = (hits.clone(), lookups.clone());
+        let g = RatioGauge::new(move || h.value() as f64, move || l.value() as f64);
+
+        hits.inc(1);
+        lookups.inc(4);
+
+        assert_eq!(Some(0.25), g.ratio());
+        match g.export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Float(0.25), x),
+            _ => panic!("expected a gauge"),
+        }
+    }
+
+    #[test]
+    fn ratio_counter_sources() {
+        let g = RatioGauge::new(AtomicCounter::with_value(3), AtomicCounter::with_value(6));
+
+        assert_eq!(Some(0.5), g.ratio());
+    }
+
+    #[test]
+    fn ratio_undefined() {
+        let zero = RatioGauge::new(|| 1.0, || 0.0);
+        assert_eq!(None, zero.ratio());
+        assert_eq!(0.0, zero.value());
+
+        let nan = RatioGauge::with_fallback(|| ::std::f64::NAN, || 1.0, -1.0);
+        assert_eq!(None, nan.ratio());
+        assert_eq!(-1.0, nan.value());
+
+        let overflow = RatioGauge::new(|| ::std::f64::MAX, || 0.5);
+        assert_eq!(None, overflow.ratio());
+    }
+
+    #[test]
+    fn ratio_of_meter_rates() {
+        let errors = MeterHandle::new();
+        let requests = MeterHandle::new();
+        let g = RatioGauge::new(MeterRate::m01rate(errors.clone()),
+                                MeterRate::m01rate(requests.clone()));
+
+        errors.mark(1);
+        requests.mark(10);
+
+        // No tick has happened yet, so both rates are still zero.
+        assert_eq!(None, g.ratio());
+
+        let g = RatioGauge::new(MeterRate::m15rate(StdMeter::new()), || 2.0);
+        assert_eq!(Some(0.0), g.ratio());
+    }
+
+    #[test]
+    fn ratio_of_ticked_meter_rates() {
+        let clock = ManualClock::new();
+        let errors = MeterHandle::from(StdMeter::with_clock(clock.clone()));
+        let requests = MeterHandle::from(StdMeter::with_clock(clock.clone()));
+        let g = RatioGauge::new(MeterRate::m01rate(errors.clone()),
+                                MeterRate::m01rate(requests.clone()));
+        let mean = RatioGauge::new(MeterRate::mean_rate(errors.clone()),
+                                   MeterRate::mean_rate(requests.clone()));
+
+        errors.mark(1);
+        requests.mark(10);
+        clock.advance(Duration::from_secs(5));
+
+        // The first tick sets both rates to their count over the interval.
+        assert_eq!(2.0, requests.m01rate());
+        assert!((g.ratio().unwrap() - 0.1).abs() < 1e-9);
+        assert!((mean.ratio().unwrap() - 0.1).abs() < 1e-9);
+
+        errors.mark(9);
+        clock.advance(Duration::from_secs(5));
+
+        // Both rates decayed by the same factor before adding their new events.
+        let decay = (-5.0f64 / 60.0).exp();
+        let alpha = 1.0 - decay;
+        let expected = (0.2 * decay + 1.8 * alpha) / (2.0 * decay);
+        assert!((g.ratio().unwrap() - expected).abs() < 1e-9);
+        assert!((mean.ratio().unwrap() - 1.0).abs() < 1e-9);
+    }
 }
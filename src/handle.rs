@@ -0,0 +1,341 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use histogram::Histogram;
+
+use counter::{AtomicCounter, StdCounter};
+use gauge::{Gauge, GaugeValue, StdGauge};
+use meter::{Clock, Meter, MeterSnapshot, StdMeter, SystemClock};
+use snapshot::HistogramSnapshot;
+use metric::{Metric, MetricValue};
+use reservoir::{HdrReservoir, Reservoir};
+use timer::{Context, Timer, TimerSnapshot};
+
+/// A cloneable handle to a counter.
+///
+/// Every clone shares the same underlying counter, so one clone can be inserted into a registry
+/// while the others keep being updated by application code.
+#[derive(Clone)]
+pub struct CounterHandle {
+    inner: Arc<AtomicCounter>
+}
+
+impl CounterHandle {
+    pub fn new() -> CounterHandle {
+        CounterHandle::from(StdCounter::new())
+    }
+
+    pub fn clear(&self) {
+        self.inner.clear();
+    }
+
+    pub fn dec(&self, value: i64) {
+        self.inner.dec(value);
+    }
+
+    pub fn inc(&self, value: i64) {
+        self.inner.inc(value);
+    }
+
+    pub fn snapshot(&self) -> StdCounter {
+        StdCounter { value: self.inner.value() }
+    }
+}
+
+impl From<StdCounter> for CounterHandle {
+    fn from(counter: StdCounter) -> CounterHandle {
+        CounterHandle { inner: Arc::new(AtomicCounter::with_value(counter.value)) }
+    }
+}
+
+impl Metric for CounterHandle {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Counter(self.snapshot())
+    }
+}
+
+/// A cloneable handle to a gauge, sharing its value between all clones.
+#[derive(Clone)]
+pub struct GaugeHandle<T = f64> {
+    inner: Arc<Mutex<StdGauge<T>>>
+}
+
+impl<T: Copy + Default> GaugeHandle<T> {
+    pub fn new() -> GaugeHandle<T> {
+        GaugeHandle::from(StdGauge { value: T::default() })
+    }
+}
+
+impl<T: Copy> GaugeHandle<T> {
+    pub fn update(&self, value: T) {
+        self.inner.lock().unwrap().update(value);
+    }
+
+    pub fn snapshot(&self) -> StdGauge<T> {
+        self.inner.lock().unwrap().snapshot()
+    }
+}
+
+impl<T> From<StdGauge<T>> for GaugeHandle<T> {
+    fn from(gauge: StdGauge<T>) -> GaugeHandle<T> {
+        GaugeHandle { inner: Arc::new(Mutex::new(gauge)) }
+    }
+}
+
+impl<T> Metric for GaugeHandle<T>
+    where T: Into<GaugeValue> + Copy + Send + Sync
+{
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Gauge(self.snapshot().value.into())
+    }
+}
+
+/// A cloneable handle to a meter.
+///
+/// Meters are already updated through `&self`, so no lock is needed here.
+pub struct MeterHandle<C: Clock = SystemClock> {
+    inner: Arc<StdMeter<C>>
+}
+
+impl MeterHandle<SystemClock> {
+    pub fn new() -> MeterHandle {
+        MeterHandle::from(StdMeter::new())
+    }
+}
+
+impl<C: Clock> Clone for MeterHandle<C> {
+    fn clone(&self) -> MeterHandle<C> {
+        MeterHandle { inner: self.inner.clone() }
+    }
+}
+
+impl<C: Clock> From<StdMeter<C>> for MeterHandle<C> {
+    fn from(meter: StdMeter<C>) -> MeterHandle<C> {
+        MeterHandle { inner: Arc::new(meter) }
+    }
+}
+
+impl<C: Clock> Meter for MeterHandle<C> {
+    fn count(&self) -> i64 {
+        self.inner.count()
+    }
+
+    fn mean_rate(&self) -> f64 {
+        self.inner.mean_rate()
+    }
+
+    fn m01rate(&self) -> f64 {
+        self.inner.m01rate()
+    }
+
+    fn m05rate(&self) -> f64 {
+        self.inner.m05rate()
+    }
+
+    fn m15rate(&self) -> f64 {
+        self.inner.m15rate()
+    }
+
+    fn mark(&self, value: i64) {
+        self.inner.mark(value)
+    }
+
+    fn snapshot(&self) -> MeterSnapshot {
+        self.inner.snapshot()
+    }
+}
+
+impl<C: Clock> Metric for MeterHandle<C> {
+    fn export_metric(&self) -> MetricValue {
+        self.inner.export_metric()
+    }
+}
+
+/// A cloneable handle to a histogram, sharing its recorded values between all clones.
+#[derive(Clone)]
+pub struct HistogramHandle {
+    inner: Arc<HdrReservoir>
+}
+
+impl HistogramHandle {
+    pub fn new(histogram: Histogram) -> HistogramHandle {
+        HistogramHandle { inner: Arc::new(HdrReservoir::new(histogram)) }
+    }
+
+    /// Records `count` occurrences of `value`.
+    pub fn record(&self, value: u64, count: u64) -> Result<(), &'static str> {
+        self.inner.record(value, count)
+    }
+
+    /// Records `value`, along with the values it missed if a value was expected every
+    /// `expected_interval`. See `snapshot::missed_values`.
+    pub fn record_with_expected_interval(&self,
+                                         value: u64,
+                                         expected_interval: u64)
+                                         -> Result<(), &'static str> {
+        self.inner.record_with_expected_interval(value, expected_interval)
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        self.inner.snapshot()
+    }
+}
+
+impl From<Histogram> for HistogramHandle {
+    fn from(histogram: Histogram) -> HistogramHandle {
+        HistogramHandle::new(histogram)
+    }
+}
+
+impl Metric for HistogramHandle {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Histogram(self.snapshot())
+    }
+}
+
+/// A cloneable handle to a timer.
+pub struct TimerHandle<R = HdrReservoir, C: Clock = SystemClock> {
+    inner: Arc<Timer<R, C>>
+}
+
+impl TimerHandle<HdrReservoir> {
+    pub fn new() -> TimerHandle {
+        TimerHandle::from(Timer::new())
+    }
+}
+
+impl<R, C: Clock> Clone for TimerHandle<R, C> {
+    fn clone(&self) -> TimerHandle<R, C> {
+        TimerHandle { inner: self.inner.clone() }
+    }
+}
+
+impl<R: Reservoir, C: Clock> TimerHandle<R, C> {
+    pub fn update(&self, duration: Duration) {
+        self.inner.update(duration);
+    }
+
+    pub fn time<F, T>(&self, f: F) -> T
+        where F: FnOnce() -> T
+    {
+        self.inner.time(f)
+    }
+
+    pub fn start(&self) -> Context<R, C> {
+        self.inner.start()
+    }
+
+    pub fn start_with_expected_interval(&self, expected_interval: Duration) -> Context<R, C> {
+        self.inner.start_with_expected_interval(expected_interval)
+    }
+
+    pub fn snapshot(&self) -> TimerSnapshot {
+        self.inner.snapshot()
+    }
+}
+
+impl<R, C: Clock> From<Timer<R, C>> for TimerHandle<R, C> {
+    fn from(timer: Timer<R, C>) -> TimerHandle<R, C> {
+        TimerHandle { inner: Arc::new(timer) }
+    }
+}
+
+impl<R: Reservoir, C: Clock> Metric for TimerHandle<R, C> {
+    fn export_metric(&self) -> MetricValue {
+        self.inner.export_metric()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use histogram::*;
+
+    use meter::Meter;
+    use metric::MetricValue;
+    use registry::{Registry, StdRegistry};
+    use super::*;
+
+    #[test]
+    fn counter_clones_share_state() {
+        let c = CounterHandle::new();
+        let c2 = c.clone();
+
+        c.inc(3);
+        c2.dec(1);
+
+        assert_eq!(2, c.snapshot().value);
+        assert_eq!(2, c2.snapshot().value);
+    }
+
+    #[test]
+    fn counter_from_threads() {
+        let c = CounterHandle::new();
+
+        let threads: Vec<_> = (0..4)
+                                  .map(|_| {
+                                      let c = c.clone();
+                                      thread::spawn(move || {
+                                          for _ in 0..1000 {
+                                              c.inc(1);
+                                          }
+                                      })
+                                  })
+                                  .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(4000, c.snapshot().value);
+    }
+
+    #[test]
+    fn updates_after_insert() {
+        let c = CounterHandle::new();
+        let g = GaugeHandle::new();
+        let m = MeterHandle::new();
+        let t = TimerHandle::new();
+        let h = HistogramHandle::new(Histogram::new(HistogramConfig {
+                                         max_memory: 0,
+                                         max_value: 1000000,
+                                         precision: 3,
+                                     })
+                                         .unwrap());
+
+        let mut r = StdRegistry::new();
+        r.insert("counter", c.clone());
+        r.insert("gauge", g.clone());
+        r.insert("meter", m.clone());
+        r.insert("histogram", h.clone());
+        r.insert("timer", t.clone());
+
+        c.inc(7);
+        g.update(1.5);
+        m.mark(3);
+        h.record(10, 2).unwrap();
+        t.time(|| ());
+
+        match r.get("counter").export_metric() {
+            MetricValue::Counter(x) => assert_eq!(7, x.value),
+            _ => panic!("expected a counter"),
+        }
+        match r.get("gauge").export_metric() {
+            MetricValue::Gauge(x) => assert_eq!(GaugeValue::Float(1.5), x),
+            _ => panic!("expected a gauge"),
+        }
+        match r.get("meter").export_metric() {
+            MetricValue::Meter(x) => assert_eq!(3, x.count),
+            _ => panic!("expected a meter"),
+        }
+        match r.get("histogram").export_metric() {
+            MetricValue::Histogram(x) => assert_eq!(2, x.count()),
+            _ => panic!("expected a histogram"),
+        }
+        match r.get("timer").export_metric() {
+            MetricValue::Timer(x) => assert_eq!(1, x.meter.count),
+            _ => panic!("expected a timer"),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::mem;
+use std::sync::RwLock;
+
+use histogram::Histogram;
+
+use metric::{Metric, MetricValue};
+use reservoir::{HdrReservoir, Reservoir, ReservoirHistogram};
+use snapshot::HistogramSnapshot;
+
+/// A histogram which only reports the values recorded since its previous report.
+///
+/// Values are recorded into an active histogram, which every snapshot swaps for a fresh one, so
+/// that percentiles follow the current distribution instead of everything since startup. Writers
+/// share a read lock on the active histogram, so they never wait on each other, and a snapshot
+/// takes the write lock only to swap it, so no value is lost or counted twice.
+///
+/// As exporting resets the histogram, it should only be read by a single reporter.
+pub struct IntervalHistogram<R = HdrReservoir> {
+    active: RwLock<ReservoirHistogram<R>>,
+    factory: Box<Fn() -> R + Send + Sync>,
+}
+
+impl IntervalHistogram<HdrReservoir> {
+    /// Creates a new histogram which starts every interval with a copy of the given, empty, HDR
+    /// histogram.
+    pub fn with_histogram(histogram: Histogram) -> IntervalHistogram {
+        IntervalHistogram::new(move || HdrReservoir::new(histogram.clone()))
+    }
+}
+
+impl<R: Reservoir> IntervalHistogram<R> {
+    /// Creates a new histogram which records every interval into a reservoir created by the given
+    /// function.
+    pub fn new<F>(factory: F) -> IntervalHistogram<R>
+        where F: Fn() -> R + Send + Sync + 'static
+    {
+        IntervalHistogram {
+            active: RwLock::new(ReservoirHistogram::new(factory())),
+            factory: Box::new(factory),
+        }
+    }
+
+    pub fn update(&self, value: u64) {
+        self.active.read().unwrap().update(value);
+    }
+
+    /// Records a value, along with the values it missed if a value was expected every
+    /// `expected_interval`. See `snapshot::missed_values`.
+    pub fn update_with_expected_interval(&self, value: u64, expected_interval: u64) {
+        self.active.read().unwrap().update_with_expected_interval(value, expected_interval);
+    }
+
+    /// Returns a snapshot of the values recorded since the previous call, and starts a new
+    /// interval.
+    pub fn snapshot_and_reset(&self) -> HistogramSnapshot {
+        let fresh = ReservoirHistogram::new((self.factory)());
+
+        // The write lock waits for the writers still recording into the previous histogram.
+        let interval = mem::replace(&mut *self.active.write().unwrap(), fresh);
+        interval.snapshot()
+    }
+}
+
+impl<R: Reservoir> Metric for IntervalHistogram<R> {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Histogram(self.snapshot_and_reset())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use histogram::*;
+
+    use metric::{Metric, MetricValue};
+    use reservoir::SlidingWindowReservoir;
+    use super::*;
+
+    fn histogram() -> Histogram {
+        Histogram::new(HistogramConfig {
+                           max_memory: 0,
+                           max_value: 1000000,
+                           precision: 3,
+                       })
+            .unwrap()
+    }
+
+    #[test]
+    fn intervals() {
+        let h = IntervalHistogram::with_histogram(histogram());
+
+        for v in 1..101 {
+            h.update(v);
+        }
+
+        let first = h.snapshot_and_reset();
+        assert_eq!(100, first.count());
+        assert_eq!(5050, first.sum());
+        assert_eq!(100, first.max());
+
+        h.update(1000);
+        h.update(2000);
+
+        let second = h.snapshot_and_reset();
+        assert_eq!(2, second.count());
+        assert_eq!(1000, second.min());
+        assert_eq!(2000, second.max());
+
+        assert_eq!(0, h.snapshot_and_reset().count());
+    }
+
+    #[test]
+    fn any_reservoir() {
+        let h = IntervalHistogram::new(|| SlidingWindowReservoir::new(10));
+        h.update(5);
+
+        assert_eq!(vec![5], h.snapshot_and_reset().values().to_vec());
+        assert!(h.snapshot_and_reset().values().is_empty());
+    }
+
+    #[test]
+    fn no_lost_values() {
+        let h = Arc::new(IntervalHistogram::with_histogram(histogram()));
+
+        let writers: Vec<_> = (0..4)
+                                  .map(|_| {
+                                      let h = h.clone();
+                                      thread::spawn(move || {
+                                          for v in 0..10000 {
+                                              h.update(v);
+                                          }
+                                      })
+                                  })
+                                  .collect();
+
+        let mut count = 0;
+        for _ in 0..100 {
+            count += h.snapshot_and_reset().count();
+        }
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        count += h.snapshot_and_reset().count();
+
+        assert_eq!(40000, count);
+    }
+
+    #[test]
+    fn export_resets() {
+        let h = IntervalHistogram::with_histogram(histogram());
+        h.update(10);
+
+        match h.export_metric() {
+            MetricValue::Histogram(s) => assert_eq!(1, s.count()),
+            _ => panic!("expected a histogram"),
+        }
+
+        match h.export_metric() {
+            MetricValue::Histogram(s) => assert_eq!(0, s.count()),
+            _ => panic!("expected a histogram"),
+        }
+    }
+}
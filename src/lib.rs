@@ -1,14 +1,27 @@
 extern crate histogram;
 extern crate num;
+extern crate rand;
 extern crate syncbox;
 extern crate time;
 
+pub mod bucket_histogram;
 pub mod counter;
+pub mod ddsketch;
 pub mod gauge;
+pub mod handle;
+pub mod interval;
 pub mod ewma;
+pub mod exponential_histogram;
 pub mod meter;
 pub mod metric;
 pub mod registry;
 pub mod reporter;
+pub mod reservoir;
+pub mod rolling;
+pub mod snapshot;
+pub mod striped;
+pub mod summary;
+pub mod tdigest;
+pub mod timer;
 pub mod carbon_reporter;
 pub mod carbon_sender;
@@ -1,18 +1,55 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use time;
 
 use syncbox::atomic::{AtomicI64, Ordering};
 
 use ewma::EWMA;
 use metric::{Metric, MetricValue};
+use striped::StripedCounter;
 
 // A MeterSnapshot
 #[derive(Debug)]
 pub struct MeterSnapshot {
     pub count: i64,
-    pub rates: [f64; 3],
+    /// The moving average rate of every window, labeled as returned by `window_label` and in the
+    /// order the windows were given to the meter.
+    pub rates: Vec<(String, f64)>,
     pub mean: f64
 }
 
+impl MeterSnapshot {
+    /// Returns the rate of the window with the given label, such as "m1".
+    pub fn rate(&self, label: &str) -> Option<f64> {
+        self.rates.iter().find(|&&(ref l, _)| l == label).map(|&(_, rate)| rate)
+    }
+}
+
+/// Returns the label of a moving average window: its length in hours, minutes, seconds,
+/// milliseconds, microseconds or nanoseconds, whichever is the largest unit dividing it, such as
+/// "m1" for one minute or "s10" for ten seconds.
+pub fn window_label(window: Duration) -> String {
+    let secs = window.as_secs();
+    let nanos = window.subsec_nanos() as u64;
+
+    if nanos % 1000 != 0 {
+        format!("ns{}", secs * 1000000000 + nanos)
+    } else if nanos % 1000000 != 0 {
+        format!("us{}", secs * 1000000 + nanos / 1000)
+    } else if nanos != 0 {
+        format!("ms{}", secs * 1000 + nanos / 1000000)
+    } else if secs % 3600 == 0 {
+        format!("h{}", secs / 3600)
+    } else if secs % 60 == 0 {
+        format!("m{}", secs / 60)
+    } else {
+        format!("s{}", secs)
+    }
+}
+
+const DEFAULT_TICK_INTERVAL: u64 = 5;
+
 /// A Meter measures the rate at which a set of events occur.
 ///
 /// Just like the Unix load averages visible in `top`.
@@ -24,30 +61,101 @@ pub trait Meter : Metric {
     fn mean_rate(&self) -> f64;
 
     /// Returns the one-minute exponentially-weighted moving average rate at which events have
-    /// occurred since the meter was created.
+    /// occurred since the meter was created, or zero if the meter has no one-minute window.
     fn m01rate(&self) -> f64;
 
     /// Returns the five-minute exponentially-weighted moving average rate at which events have
-    /// occurred since the meter was created.
+    /// occurred since the meter was created, or zero if the meter has no five-minute window.
     fn m05rate(&self) -> f64;
 
     /// Returns the fifteen-minute exponentially-weighted moving average rate at which events have
-    /// occurred since the meter was created.
+    /// occurred since the meter was created, or zero if the meter has no fifteen-minute window.
     fn m15rate(&self) -> f64;
 
     /// Mark the occurrence of a given number of events.
     fn mark(&self, value: i64);
+
+    /// Returns the count, moving average rates and mean rate, all taken at the same instant.
+    fn snapshot(&self) -> MeterSnapshot;
 }
 
+/// A source of time for metrics which depend on it.
 pub trait Clock: Send + Sync {
+    /// Returns the current time in nanoseconds, counted from an arbitrary origin.
+    ///
+    /// Only differences between two readings are meaningful. They should never be negative, but
+    /// users of a clock must tolerate it stepping backwards.
     fn now(&self) -> i64;
 }
 
-struct SystemClock;
+/// The number of nanoseconds in a second, the unit of `Clock`.
+pub const NANOS_PER_SEC: i64 = 1000000000;
+
+/// Converts a duration to nanoseconds, the unit of `Clock`.
+///
+/// Durations longer than i64::MAX nanoseconds, about 292 years, are clamped to it.
+pub fn to_nanos(duration: Duration) -> i64 {
+    let secs = duration.as_secs();
+
+    if secs > i64::max_value() as u64 {
+        return i64::max_value();
+    }
+
+    (secs as i64)
+        .checked_mul(NANOS_PER_SEC)
+        .and_then(|nanos| nanos.checked_add(duration.subsec_nanos() as i64))
+        .unwrap_or(i64::max_value())
+}
+
+/// A clock backed by the system monotonic clock, with nanosecond resolution.
+///
+/// Unlike the wall clock, it isn't affected by changes of the system time, such as NTP steps.
+pub struct SystemClock;
 
 impl Clock for SystemClock {
     fn now(&self) -> i64 {
-        time::get_time().sec
+        time::precise_time_ns() as i64
+    }
+}
+
+/// A clock which only moves when told to, for deterministic tests of time dependent metrics.
+///
+/// Clones share the same time, so a clone can be handed to a metric while the test keeps
+/// advancing the original.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    /// Creates a new clock at time zero.
+    pub fn new() -> ManualClock {
+        ManualClock { now: Arc::new(AtomicI64::new(0)) }
+    }
+
+    /// Moves the clock forwards by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        let duration = to_nanos(duration);
+
+        loop {
+            let old = self.now.load(Ordering::SeqCst);
+            let now = old.saturating_add(duration);
+
+            if self.now.compare_and_swap(old, now, Ordering::SeqCst) == old {
+                break;
+            }
+        }
+    }
+
+    /// Sets the time in nanoseconds, which can also move the clock backwards.
+    pub fn set(&self, nanos: i64) {
+        self.now.store(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
     }
 }
 
@@ -57,107 +165,201 @@ pub struct StdMeter<C: Clock = SystemClock> {
 
     birthstamp: i64,
     prev: AtomicI64,
+    // In nanoseconds.
+    tick_interval: i64,
 
-    count: AtomicI64,
-    rates: [EWMA; 3],
+    count: StripedCounter,
+    rates: Vec<(String, EWMA)>,
 }
 
 impl<C: Clock> StdMeter<C> {
-    fn with(clock: C) -> StdMeter<C> {
+    /// Creates a new meter with one, five and fifteen minute moving averages, ticked every 5
+    /// seconds, which reads the time from the given clock.
+    pub fn with_clock(clock: C) -> StdMeter<C> {
+        StdMeter::with_windows_and_clock(&default_windows(),
+                                         Duration::from_secs(DEFAULT_TICK_INTERVAL),
+                                         clock)
+    }
+
+    /// Creates a new meter with a moving average for each of the given windows, ticked every
+    /// `tick_interval`, which reads the time from the given clock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tick interval is zero, or if two windows have the same label.
+    pub fn with_windows_and_clock(windows: &[Duration],
+                                  tick_interval: Duration,
+                                  clock: C)
+                                  -> StdMeter<C> {
+        assert!(to_nanos(tick_interval) > 0, "the tick interval must not be zero");
+
+        let labels: Vec<_> = windows.iter().map(|&w| window_label(w)).collect();
+        for (i, label) in labels.iter().enumerate() {
+            assert!(!labels[..i].contains(label), "duplicate window {}", label);
+        }
+
         let birthstamp = clock.now();
 
         StdMeter {
-            count: AtomicI64::new(0),
+            count: StripedCounter::new(),
             clock: clock,
             birthstamp: birthstamp,
             prev: AtomicI64::new(birthstamp),
-            rates: [EWMA::m01rate(), EWMA::m05rate(), EWMA::m15rate()],
+            tick_interval: to_nanos(tick_interval),
+            rates: labels.into_iter()
+                         .zip(windows)
+                         .map(|(label, &w)| (label, EWMA::with_window(w, tick_interval)))
+                         .collect(),
         }
     }
 
+    /// Returns the clock the meter reads the time from.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
     fn tick_maybe(&self) {
         let now = self.clock.now();
+        self.tick_maybe_at(now);
+    }
+
+    fn tick_maybe_at(&self, now: i64) {
         let old = self.prev.load(Ordering::SeqCst);
         let elapsed = now - old;
 
-        if elapsed > 5 {
-            // Clock values should monotonically increase, so no ABA problem here is possible.
-            if self.prev.compare_and_swap(old, now - elapsed % 5, Ordering::SeqCst) == old {
-                let ticks = elapsed / 5;
+        if elapsed < 0 {
+            // The clock stepped backwards. Restart from the current time, instead of waiting for
+            // the clock to catch up with the previous tick.
+            self.prev.compare_and_swap(old, now, Ordering::SeqCst);
+        } else if elapsed >= self.tick_interval {
+            // As long as the clock doesn't step backwards by exactly a multiple of the tick
+            // interval, an ABA problem isn't possible here.
+            let next = now - elapsed % self.tick_interval;
 
-                for _ in 0..ticks {
-                    for rate in &self.rates {
-                        rate.tick();
-                    }
+            if self.prev.compare_and_swap(old, next, Ordering::SeqCst) == old {
+                let ticks = elapsed / self.tick_interval;
+
+                for &(_, ref rate) in &self.rates {
+                    rate.tick_n(ticks as u64);
                 }
             }
         }
     }
+
+    fn mean_rate_at(&self, count: i64, now: i64) -> f64 {
+        let elapsed = now - self.birthstamp;
+
+        if count == 0 || elapsed <= 0 {
+            return 0.0;
+        }
+
+        count as f64 / (elapsed as f64 / NANOS_PER_SEC as f64)
+    }
+
+    fn window_rate(&self, label: &str) -> f64 {
+        self.tick_maybe();
+
+        self.rates
+            .iter()
+            .find(|&&(ref l, _)| l == label)
+            .map_or(0.0, |&(_, ref rate)| rate.rate())
+    }
+}
+
+fn default_windows() -> [Duration; 3] {
+    [Duration::from_secs(60), Duration::from_secs(5 * 60), Duration::from_secs(15 * 60)]
 }
 
 impl StdMeter<SystemClock> {
+    /// Creates a new meter with one, five and fifteen minute moving averages, ticked every 5
+    /// seconds.
     pub fn new() -> StdMeter {
-        StdMeter::with(SystemClock)
+        StdMeter::with_clock(SystemClock)
+    }
+
+    /// Creates a new meter with a moving average for each of the given windows, ticked every
+    /// `tick_interval`.
+    ///
+    /// A shorter tick interval makes the rates follow changes sooner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tick interval is zero.
+    pub fn with_windows(windows: &[Duration], tick_interval: Duration) -> StdMeter {
+        StdMeter::with_windows_and_clock(windows, tick_interval, SystemClock)
     }
 }
 
 impl<C: Clock> Meter for StdMeter<C> {
     fn count(&self) -> i64 {
-        self.count.load(Ordering::SeqCst)
+        self.count.sum()
     }
 
     fn mean_rate(&self) -> f64 {
-        let count = self.count.load(Ordering::SeqCst);
+        let count = self.count.sum();
 
         if count == 0 {
             return 0.0;
         }
 
-        let elapsed = self.clock.now() - self.birthstamp;
-
-        count as f64 / elapsed as f64
+        self.mean_rate_at(count, self.clock.now())
     }
 
     fn m01rate(&self) -> f64 {
-        self.tick_maybe();
-        self.rates[0].rate()
+        self.window_rate("m1")
     }
 
     fn m05rate(&self) -> f64 {
-        self.tick_maybe();
-        self.rates[1].rate()
+        self.window_rate("m5")
     }
 
     fn m15rate(&self) -> f64 {
-        self.tick_maybe();
-        self.rates[2].rate()
+        self.window_rate("m15")
     }
 
     fn mark(&self, value: i64) {
         self.tick_maybe();
 
-        self.count.fetch_add(value, Ordering::SeqCst);
+        self.count.add(value);
 
-        for rate in &self.rates {
+        for &(_, ref rate) in &self.rates {
             rate.update(value);
         }
     }
+
+    fn snapshot(&self) -> MeterSnapshot {
+        let now = self.clock.now();
+        self.tick_maybe_at(now);
+
+        let count = self.count.sum();
+
+        MeterSnapshot {
+            count: count,
+            rates: self.rates.iter().map(|&(ref label, ref rate)| (label.clone(), rate.rate())).collect(),
+            mean: self.mean_rate_at(count, now),
+        }
+    }
 }
 
 impl<C: Clock> Metric for StdMeter<C> {
     fn export_metric(&self) -> MetricValue {
-        unimplemented!();
+        MetricValue::Meter(self.snapshot())
     }
 }
 
 #[cfg(test)]
 mod test {
     use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use metric::{Metric, MetricValue};
     use super::*;
 
+    const SEC: i64 = NANOS_PER_SEC;
+
     macro_rules! assert_float_eq {
         ($x:expr, $y:expr, $d:expr) => {
-            if !($x - $y < $d || $y - $x < $d) { panic!(); }
+            if ($x - $y).abs() > $d { panic!("{} != {}", $x, $y); }
         }
     }
 
@@ -185,12 +387,12 @@ mod test {
             fn now(&self) -> i64 {
                 match self.counter.fetch_add(1, Ordering::SeqCst) {
                     0 | 1 => 0,
-                    _ => 10,
+                    _ => 10 * NANOS_PER_SEC,
                 }
             }
         }
 
-        let meter = StdMeter::with(MockClock { counter: AtomicUsize::new(0) });
+        let meter = StdMeter::with_clock(MockClock { counter: AtomicUsize::new(0) });
 
         meter.mark(1);
         meter.mark(2);
@@ -206,25 +408,245 @@ mod test {
         assert_eq!(7, meter.clock.counter.load(Ordering::SeqCst));
     }
 
-    // #[test]
-    // fn snapshot() {
-    //     let m: StdMeter = StdMeter::new();
-    //     m.mark(1);
-    //     m.mark(1);
-    //
-    //     let s = m.snapshot();
-    //
-    //     m.mark(1);
-    //
-    //     assert_eq!(s.count, 2);
-    //     assert_eq!(m.snapshot().count, 3);
-    // }
-    //
-    // // Test that decay works correctly
-    // #[test]
-    // fn decay() {
-    //     let mut m: StdMeter = StdMeter::new();
-    //
-    //     m.tick();
-    // }
+    #[test]
+    fn snapshot() {
+        let m = StdMeter::new();
+        m.mark(1);
+        m.mark(1);
+
+        let s = m.snapshot();
+
+        m.mark(1);
+
+        assert_eq!(s.count, 2);
+        assert_eq!(m.snapshot().count, 3);
+    }
+
+    // Test that decay works correctly
+    #[test]
+    fn decay() {
+        let m = StdMeter::with_clock(ManualClock::new());
+        m.mark(60);
+
+        m.clock.set(10 * SEC);
+        let first = m.snapshot();
+
+        m.clock.set(70 * SEC);
+        let second = m.snapshot();
+
+        assert_eq!(60, second.count);
+        for (&(_, second), &(_, first)) in second.rates.iter().zip(&first.rates) {
+            assert!(second < first);
+        }
+        assert_float_eq!(60.0 / 70.0, second.mean, 1e-3);
+    }
+
+    #[test]
+    fn custom_windows() {
+        let windows = [Duration::from_secs(10), Duration::from_secs(60), Duration::from_secs(3600)];
+        let m = StdMeter::with_windows_and_clock(&windows,
+                                                 Duration::from_secs(1),
+                                                 ManualClock::new());
+        m.mark(10);
+
+        m.clock.set(SEC);
+        let s = m.snapshot();
+
+        let labels: Vec<_> = s.rates.iter().map(|&(ref label, _)| label.clone()).collect();
+        assert_eq!(vec!["s10", "m1", "h1"], labels);
+
+        // The first tick sets every rate to the rate of its interval.
+        for &(_, rate) in &s.rates {
+            assert_float_eq!(10.0, rate, 1e-9);
+        }
+
+        // After ten seconds, the ten second window decayed the most.
+        m.clock.set(11 * SEC);
+        let s = m.snapshot();
+
+        assert!(s.rate("s10").unwrap() < s.rate("m1").unwrap());
+        assert!(s.rate("m1").unwrap() < s.rate("h1").unwrap());
+        assert_eq!(None, s.rate("m5"));
+        assert_eq!(s.rate("m1").unwrap(), m.m01rate());
+        assert_eq!(0.0, m.m05rate());
+    }
+
+    #[test]
+    fn sub_second_mean_rate() {
+        let m = StdMeter::with_clock(ManualClock::new());
+        m.mark(3);
+
+        m.clock.set(SEC / 2);
+        assert_float_eq!(6.0, m.mean_rate(), 1e-9);
+
+        m.clock.set(3 * SEC / 2);
+        assert_float_eq!(2.0, m.mean_rate(), 1e-9);
+    }
+
+    #[test]
+    fn sub_second_ticks() {
+        let windows = [Duration::from_secs(1)];
+        let m = StdMeter::with_windows_and_clock(&windows,
+                                                 Duration::from_millis(100),
+                                                 ManualClock::new());
+        m.mark(1);
+
+        // Not a whole tick yet.
+        m.clock.set(SEC / 20);
+        assert_eq!(0.0, m.snapshot().rates[0].1);
+
+        // The first tick sees one event in 100ms.
+        m.clock.set(SEC / 10);
+        assert_float_eq!(10.0, m.snapshot().rates[0].1, 1e-9);
+    }
+
+    #[test]
+    fn clock_steps_backwards() {
+        let windows = [Duration::from_secs(60)];
+        let m = StdMeter::with_windows_and_clock(&windows,
+                                                 Duration::from_secs(5),
+                                                 ManualClock::new());
+
+        m.clock.set(100 * SEC);
+        m.mark(5);
+        m.clock.set(105 * SEC);
+        let before = m.snapshot().rates[0].1;
+
+        // The marks land after twenty empty ticks, so the first tick counting them only moves the
+        // rate by alpha.
+        assert_float_eq!(1.0 - (-5.0f64 / 60.0).exp(), before, 1e-9);
+
+        // Stepping backwards neither ticks nor breaks the rates.
+        m.clock.set(50 * SEC);
+        let s = m.snapshot();
+        assert_eq!(before, s.rates[0].1);
+        assert_eq!(5, s.count);
+
+        // Ticking resumes one interval after the step, rather than once the clock is back at 110.
+        m.clock.set(55 * SEC);
+        assert!(m.snapshot().rates[0].1 < before);
+    }
+
+    #[test]
+    fn clock_steps_forwards() {
+        let windows = [Duration::from_secs(60)];
+        let m = StdMeter::with_windows_and_clock(&windows,
+                                                 Duration::from_secs(5),
+                                                 ManualClock::new());
+        m.mark(5);
+
+        m.clock.set(5 * SEC);
+        let first = m.snapshot().rates[0].1;
+
+        // A jump of an hour decays the rate as much as an hour of ticks would.
+        m.clock.set(3605 * SEC);
+        let expected = first * (-3600.0f64 / 60.0).exp();
+        assert_float_eq!(expected, m.snapshot().rates[0].1, 1e-12);
+    }
+
+    #[test]
+    fn long_idle_with_short_ticks() {
+        let windows = [Duration::from_secs(60)];
+        let m = StdMeter::with_windows_and_clock(&windows,
+                                                 Duration::from_millis(1),
+                                                 ManualClock::new());
+        m.mark(5);
+
+        m.clock.set(SEC / 1000);
+        let first = m.snapshot().rates[0].1;
+
+        // A minute of millisecond ticks decays the rate in one step.
+        m.clock.set(SEC / 1000 + 60 * SEC);
+        assert_float_eq!(first * (-1.0f64).exp(), m.snapshot().rates[0].1, 1e-6);
+
+        // So do a hundred years of them.
+        m.clock.advance(Duration::from_secs(100 * 365 * 86400));
+        assert_eq!(0.0, m.snapshot().rates[0].1);
+    }
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new();
+        let m = StdMeter::with_clock(clock.clone());
+        m.mark(10);
+
+        clock.advance(Duration::from_secs(5));
+        assert_float_eq!(2.0, m.mean_rate(), 1e-9);
+        assert_float_eq!(2.0, m.m01rate(), 1e-9);
+
+        clock.advance(Duration::from_secs(5));
+        assert_float_eq!(1.0, m.mean_rate(), 1e-9);
+        assert!(m.m01rate() < 2.0);
+
+        clock.set(0);
+        assert_eq!(0, m.clock().now());
+        assert_eq!(0.0, m.mean_rate());
+    }
+
+    #[test]
+    fn system_clock() {
+        let clock = SystemClock;
+        let first = clock.now();
+        ::std::thread::sleep(Duration::from_millis(1));
+
+        assert!(clock.now() - first >= 1000000);
+    }
+
+    #[test]
+    fn nanos() {
+        assert_eq!(1500000000, to_nanos(Duration::from_millis(1500)));
+        assert_eq!(::std::i64::MAX, to_nanos(Duration::new(9223372036, 854775808)));
+        assert_eq!(::std::i64::MAX, to_nanos(Duration::new(::std::u64::MAX, 0)));
+    }
+
+    #[test]
+    fn manual_clock_saturates() {
+        let clock = ManualClock::new();
+        clock.advance(Duration::from_secs(1));
+        clock.advance(Duration::new(::std::u64::MAX, 0));
+
+        assert_eq!(::std::i64::MAX, clock.now());
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!("m1", window_label(Duration::from_secs(60)));
+        assert_eq!("m15", window_label(Duration::from_secs(900)));
+        assert_eq!("s90", window_label(Duration::from_secs(90)));
+        assert_eq!("h1", window_label(Duration::from_secs(3600)));
+        assert_eq!("ms500", window_label(Duration::from_millis(500)));
+        assert_eq!("ms1500", window_label(Duration::from_millis(1500)));
+        assert_eq!("us500", window_label(Duration::new(0, 500000)));
+        assert_eq!("ns1500", window_label(Duration::new(0, 1500)));
+    }
+
+    #[test]
+    fn sub_millisecond_windows() {
+        let windows = [Duration::new(0, 500000), Duration::new(0, 900000)];
+        let m = StdMeter::with_windows_and_clock(&windows,
+                                                 Duration::new(0, 100000),
+                                                 ManualClock::new());
+
+        let s = m.snapshot();
+        assert_eq!("us500", s.rates[0].0);
+        assert_eq!("us900", s.rates[1].0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate_windows() {
+        let windows = [Duration::from_secs(60), Duration::from_secs(60)];
+        StdMeter::with_windows_and_clock(&windows, Duration::from_secs(5), ManualClock::new());
+    }
+
+    #[test]
+    fn export() {
+        let m = StdMeter::new();
+        m.mark(5);
+
+        match m.export_metric() {
+            MetricValue::Meter(s) => assert_eq!(5, s.count),
+            _ => panic!("expected a meter snapshot"),
+        }
+    }
 }
@@ -1,6 +1,13 @@
+use bucket_histogram::BucketSnapshot;
 use counter::StdCounter;
-use gauge::StdGauge;
+use ddsketch::DDSketch;
+use exponential_histogram::ExponentialSnapshot;
+use gauge::GaugeValue;
 use meter::MeterSnapshot;
+use rolling::RollingSnapshot;
+use snapshot::HistogramSnapshot;
+use summary::SummarySnapshot;
+use timer::TimerSnapshot;
 /// a Metric
 use histogram::Histogram;
 
@@ -10,12 +17,23 @@ pub trait Metric: Send + Sync {
 
 impl Metric for Histogram {
     fn export_metric(&self) -> MetricValue {
-        MetricValue::Histogram(self.clone())
+        // Reading percentiles needs a mutable histogram, and a histogram registered by value is
+        // only reachable through `&self`, so it has to be copied. Its sum is estimated as well.
+        // Register a `HistogramHandle` instead, which is read under its lock without a copy and
+        // tracks the exact sum.
+        let mut histogram = self.clone();
+        MetricValue::Histogram(HistogramSnapshot::from_histogram(&mut histogram))
     }
 }
 pub enum MetricValue {
     Counter(StdCounter),
-    Gauge(StdGauge),
+    Gauge(GaugeValue),
     Meter(MeterSnapshot),
-    Histogram(Histogram)
+    Histogram(HistogramSnapshot),
+    Timer(TimerSnapshot),
+    Buckets(BucketSnapshot),
+    Exponential(ExponentialSnapshot),
+    Sketch(DDSketch),
+    Summary(SummarySnapshot),
+    Rolling(RollingSnapshot)
 }
@@ -3,6 +3,7 @@ use registry::{Registry, StdRegistry};
 use std::thread;
 use std::time::Duration;
 use std::sync::Arc;
+use time::{self, Timespec};
 
 pub trait Reporter: Send + Sync {
     fn report(&self, delay_ms: u32);
@@ -10,6 +11,35 @@ pub trait Reporter: Send + Sync {
     fn get_unique_reporter_name(&self) -> &'static str;
 }
 
+/// A source of the timestamps reporters attach to the metrics they send.
+///
+/// Unlike a `Clock`, which only measures durations from an arbitrary origin, it tells the time
+/// since the Unix epoch. Closures returning a `Timespec` are sources too, which is handy for fixed
+/// timestamps in tests.
+pub trait TimestampSource: Send + Sync {
+    fn timestamp(&self) -> Timespec;
+}
+
+impl<F> TimestampSource for F
+    where F: Fn() -> Timespec + Send + Sync
+{
+    fn timestamp(&self) -> Timespec {
+        self()
+    }
+}
+
+/// The system wall clock.
+///
+/// It follows changes of the system time, so it's only meant for timestamps and not for measuring
+/// durations.
+pub struct WallClock;
+
+impl TimestampSource for WallClock {
+    fn timestamp(&self) -> Timespec {
+        time::get_time()
+    }
+}
+
 pub struct ConsoleReporter {
     registry: Arc<StdRegistry<'static>>,
     reporter_name: &'static str
@@ -17,7 +47,7 @@ pub struct ConsoleReporter {
 
 impl Reporter for ConsoleReporter {
     fn report(&self, delay_ms: u32) {
-        use metric::MetricValue::{Counter, Gauge, Histogram, Meter};
+        use metric::MetricValue::{Buckets, Counter, Exponential, Gauge, Histogram, Meter, Rolling, Sketch, Summary, Timer};
         let registry = self.registry.clone();
         thread::spawn(move || {
                                loop {
@@ -28,13 +58,34 @@ impl Reporter for ConsoleReporter {
                                                println!("{:?}", x);
                                            }
                                            Gauge(x) => {
-                                               println!("{:?}", x);
+                                               println!("{}", x);
                                            }
                                            Counter(x) => {
                                                println!("{:?}", x);
                                            }
                                            Histogram(x) => {
-                                               println!("histogram{:?}", x);
+                                               println!("histogram {}", x);
+                                           }
+                                           Timer(x) => {
+                                               println!("timer {:?} {}", x.meter, x.histogram);
+                                           }
+                                           Buckets(x) => {
+                                               println!("{:?}", x);
+                                           }
+                                           Exponential(x) => {
+                                               println!("exponential min: {}, max: {}, {:?}",
+                                                        x.min(),
+                                                        x.max(),
+                                                        x.summary());
+                                           }
+                                           Sketch(x) => {
+                                               println!("{:?}", x);
+                                           }
+                                           Summary(x) => {
+                                               println!("{:?}", x);
+                                           }
+                                           Rolling(x) => {
+                                               println!("{:?}", x);
                                            }
                                        }
                                    }
@@ -64,13 +115,20 @@ mod test {
     use meter::{Meter, StdMeter};
     use counter::{Counter, StdCounter};
     use gauge::{Gauge, StdGauge};
+    use handle::GaugeHandle;
     use registry::{Registry, StdRegistry};
-    use reporter::{ConsoleReporter};
+    use reporter::{ConsoleReporter, TimestampSource, WallClock};
     use std::sync::Arc;
     use std::time::Duration;
     use std::thread;
     use histogram::*;
 
+    #[test]
+    fn wall_clock() {
+        // Later than 2015.
+        assert!(WallClock.timestamp().sec > 1420070400);
+    }
+
     #[test]
     fn meter() {
         let m = StdMeter::new();
@@ -81,6 +139,7 @@ mod test {
 
         let mut g: StdGauge = StdGauge { value: 0f64 };
         g.update(1.2);
+        let g = GaugeHandle::from(g);
 
         let mut h = Histogram::new(
     HistogramConfig{
@@ -94,7 +153,7 @@ mod test {
         let mut r = StdRegistry::new();
         r.insert("meter1", m);
         r.insert("counter1", c);
-        r.insert("gauge1", g);
+        r.insert("gauge1", g.clone());
         r.insert("histogram", h);
 
         let arc_registry = Arc::new(r);
@@ -0,0 +1,840 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use histogram::Histogram;
+use rand::{self, Rng};
+use syncbox::atomic::{AtomicI64, Ordering};
+
+use meter::{self, Clock, NANOS_PER_SEC, SystemClock};
+use metric::{Metric, MetricValue};
+use snapshot::{self, HistogramSnapshot};
+
+/// A Reservoir keeps a statistically representative sample of a stream of values.
+pub trait Reservoir: Send + Sync {
+    /// Returns the number of values currently held by the reservoir.
+    fn size(&self) -> usize;
+
+    /// Adds a new recorded value to the reservoir.
+    fn update(&self, value: u64);
+
+    /// Adds a new recorded value to the reservoir, or returns an error if the reservoir can't
+    /// represent it and drops it.
+    fn try_update(&self, value: u64) -> Result<(), &'static str> {
+        self.update(value);
+        Ok(())
+    }
+
+    /// Returns a snapshot of the values in the reservoir.
+    fn snapshot(&self) -> HistogramSnapshot;
+}
+
+impl<R: Reservoir + ?Sized> Reservoir for Box<R> {
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn update(&self, value: u64) {
+        (**self).update(value)
+    }
+
+    fn try_update(&self, value: u64) -> Result<(), &'static str> {
+        (**self).try_update(value)
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        (**self).snapshot()
+    }
+}
+
+/// A histogram which keeps its values in a reservoir.
+///
+/// Percentiles are computed from the values in the reservoir, while the count and the sum cover
+/// every value which has ever been recorded. Values which the reservoir drops, such as those
+/// beyond the maximum of an HDR histogram, aren't counted either.
+pub struct ReservoirHistogram<R> {
+    reservoir: R,
+    count: AtomicI64,
+    sum: AtomicI64,
+}
+
+impl<R: Reservoir> ReservoirHistogram<R> {
+    pub fn new(reservoir: R) -> ReservoirHistogram<R> {
+        ReservoirHistogram {
+            reservoir: reservoir,
+            count: AtomicI64::new(0),
+            sum: AtomicI64::new(0),
+        }
+    }
+
+    pub fn update(&self, value: u64) {
+        if self.reservoir.try_update(value).is_ok() {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.sum.fetch_add(value as i64, Ordering::SeqCst);
+        }
+    }
+
+    /// Records a value, along with the values it missed if a value was expected every
+    /// `expected_interval`, to correct for coordinated omission. See `snapshot::missed_values`.
+    pub fn update_with_expected_interval(&self, value: u64, expected_interval: u64) {
+        self.update(value);
+        snapshot::missed_values(value, expected_interval, |missed| self.update(missed));
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        self.reservoir
+            .snapshot()
+            .with_totals(self.count.load(Ordering::SeqCst) as u64,
+                         self.sum.load(Ordering::SeqCst) as u64)
+    }
+}
+
+impl<R: Reservoir> Metric for ReservoirHistogram<R> {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Histogram(self.snapshot())
+    }
+}
+
+struct HdrState {
+    histogram: Histogram,
+    // The HDR histogram doesn't track the sum of its values.
+    sum: u64,
+}
+
+impl HdrState {
+    fn record(&mut self, value: u64, count: u64) -> Result<(), &'static str> {
+        try!(self.histogram.record(value, count));
+        self.sum = self.sum.saturating_add(value.saturating_mul(count));
+        Ok(())
+    }
+}
+
+/// A reservoir which records every value into an HDR histogram.
+///
+/// Values which exceed the maximum value of the histogram are dropped. The sum of the recorded
+/// values is tracked alongside the histogram, so snapshots report it exactly.
+pub struct HdrReservoir {
+    state: Mutex<HdrState>,
+}
+
+impl HdrReservoir {
+    pub fn new(histogram: Histogram) -> HdrReservoir {
+        HdrReservoir {
+            state: Mutex::new(HdrState {
+                histogram: histogram,
+                sum: 0,
+            }),
+        }
+    }
+
+    /// Records `count` occurrences of `value`.
+    pub fn record(&self, value: u64, count: u64) -> Result<(), &'static str> {
+        self.state.lock().unwrap().record(value, count)
+    }
+
+    /// Records `value`, along with the values it missed if a value was expected every
+    /// `expected_interval`. See `snapshot::missed_values`.
+    pub fn record_with_expected_interval(&self,
+                                         value: u64,
+                                         expected_interval: u64)
+                                         -> Result<(), &'static str> {
+        let mut state = self.state.lock().unwrap();
+        let mut result = state.record(value, 1);
+
+        snapshot::missed_values(value, expected_interval, |missed| {
+            result = result.and(state.record(missed, 1));
+        });
+
+        result
+    }
+}
+
+impl Reservoir for HdrReservoir {
+    fn size(&self) -> usize {
+        self.state.lock().unwrap().histogram.count() as usize
+    }
+
+    fn update(&self, value: u64) {
+        let _ = self.record(value, 1);
+    }
+
+    fn try_update(&self, value: u64) -> Result<(), &'static str> {
+        self.record(value, 1)
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let mut state = self.state.lock().unwrap();
+        let sum = state.sum;
+        let count = state.histogram.count();
+
+        HistogramSnapshot::from_histogram(&mut state.histogram).with_totals(count, sum)
+    }
+}
+
+const DEFAULT_SIZE: usize = 1028;
+const DEFAULT_ALPHA: f64 = 0.015;
+// In nanoseconds.
+const RESCALE_THRESHOLD: i64 = 60 * 60 * NANOS_PER_SEC;
+
+struct WeightedSample {
+    priority: f64,
+    value: u64,
+    weight: f64,
+}
+
+impl PartialEq for WeightedSample {
+    fn eq(&self, other: &WeightedSample) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl Eq for WeightedSample {}
+
+impl PartialOrd for WeightedSample {
+    fn partial_cmp(&self, other: &WeightedSample) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, so that the sample with the lowest priority is at the top of the heap.
+impl Ord for WeightedSample {
+    fn cmp(&self, other: &WeightedSample) -> CmpOrdering {
+        other.priority.partial_cmp(&self.priority).unwrap_or(CmpOrdering::Equal)
+    }
+}
+
+struct ExpDecayState {
+    samples: BinaryHeap<WeightedSample>,
+    start_time: i64,
+    next_scale_time: i64,
+}
+
+/// An exponentially decaying reservoir, biased towards the last five minutes of values.
+///
+/// Values are sampled with a priority which grows exponentially with the time they were
+/// recorded at, so that older values are gradually pushed out by newer ones. Priorities are
+/// rescaled every hour to keep them from overflowing.
+///
+/// \see http://dimacs.rutgers.edu/~graham/pubs/papers/fwddecay.pdf Forward Decay: A Practical
+/// Time Decay Model for Streaming Systems
+pub struct ExpDecayReservoir<C: Clock = SystemClock> {
+    size: usize,
+    alpha: f64,
+    clock: C,
+    state: Mutex<ExpDecayState>,
+}
+
+impl ExpDecayReservoir<SystemClock> {
+    /// Creates a new reservoir of 1028 values with an alpha of 0.015, which offers a 99.9%
+    /// confidence level with a 5% margin of error assuming a normal distribution.
+    pub fn new() -> ExpDecayReservoir {
+        ExpDecayReservoir::with_size(DEFAULT_SIZE, DEFAULT_ALPHA)
+    }
+
+    /// Creates a new reservoir with the given size and decay factor.
+    ///
+    /// The higher the alpha, the more biased the reservoir is towards newer values.
+    pub fn with_size(size: usize, alpha: f64) -> ExpDecayReservoir {
+        ExpDecayReservoir::with_clock(size, alpha, SystemClock)
+    }
+}
+
+impl<C: Clock> ExpDecayReservoir<C> {
+    /// Creates a new reservoir with the given size and decay factor, which reads the time from
+    /// the given clock.
+    pub fn with_clock(size: usize, alpha: f64, clock: C) -> ExpDecayReservoir<C> {
+        let now = clock.now();
+
+        ExpDecayReservoir {
+            size: size,
+            alpha: alpha,
+            clock: clock,
+            state: Mutex::new(ExpDecayState {
+                samples: BinaryHeap::with_capacity(size),
+                start_time: now,
+                next_scale_time: now + RESCALE_THRESHOLD,
+            }),
+        }
+    }
+
+    // The alpha is per second, while t is in nanoseconds.
+    fn weight(&self, t: i64) -> f64 {
+        (self.alpha * t as f64 / NANOS_PER_SEC as f64).exp()
+    }
+
+    // Priorities and weights are relative to a landmark, the start time. Moving the landmark
+    // to now and scaling every priority and weight by exp(-alpha * (now - old start time))
+    // leaves their ratios unchanged, while keeping them from growing without bound.
+    fn rescale(&self, state: &mut ExpDecayState, now: i64) {
+        let old_start_time = state.start_time;
+        state.start_time = now;
+        state.next_scale_time = now + RESCALE_THRESHOLD;
+
+        let scale = self.weight(old_start_time - now);
+
+        let samples = ::std::mem::replace(&mut state.samples, BinaryHeap::with_capacity(self.size));
+        state.samples.extend(samples.into_iter()
+                                    .map(|s| {
+                                        WeightedSample {
+                                            priority: s.priority * scale,
+                                            value: s.value,
+                                            weight: s.weight * scale,
+                                        }
+                                    })
+                                    .filter(|s| s.weight > 0.0));
+    }
+}
+
+impl<C: Clock> Reservoir for ExpDecayReservoir<C> {
+    fn size(&self) -> usize {
+        self.state.lock().unwrap().samples.len()
+    }
+
+    fn update(&self, value: u64) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+
+        if now >= state.next_scale_time {
+            self.rescale(&mut state, now);
+        }
+
+        let weight = self.weight(now - state.start_time);
+        // Never zero, so the priority stays finite.
+        let random = 1.0 - rand::random::<f64>();
+        let sample = WeightedSample {
+            priority: weight / random,
+            value: value,
+            weight: weight,
+        };
+
+        if state.samples.len() < self.size {
+            state.samples.push(sample);
+        } else if state.samples.peek().map_or(false, |lowest| sample.priority > lowest.priority) {
+            state.samples.pop();
+            state.samples.push(sample);
+        }
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let state = self.state.lock().unwrap();
+
+        HistogramSnapshot::weighted(state.samples.iter().map(|s| (s.value, s.weight)).collect())
+    }
+}
+
+struct UniformState {
+    values: Vec<u64>,
+    // The number of values offered to the reservoir so far.
+    count: u64,
+}
+
+/// A reservoir which keeps a uniform random sample of every value it has seen.
+///
+/// \see http://www.cs.umd.edu/~samir/498/vitter.pdf Random Sampling with a Reservoir
+pub struct UniformReservoir {
+    size: usize,
+    state: Mutex<UniformState>,
+}
+
+impl UniformReservoir {
+    /// Creates a new reservoir of 1028 values, which offers a 99.9% confidence level with a 5%
+    /// margin of error assuming a normal distribution.
+    pub fn new() -> UniformReservoir {
+        UniformReservoir::with_size(DEFAULT_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> UniformReservoir {
+        UniformReservoir {
+            size: size,
+            state: Mutex::new(UniformState {
+                values: Vec::with_capacity(size),
+                count: 0,
+            }),
+        }
+    }
+}
+
+impl Reservoir for UniformReservoir {
+    fn size(&self) -> usize {
+        self.state.lock().unwrap().values.len()
+    }
+
+    fn update(&self, value: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.count += 1;
+
+        if state.values.len() < self.size {
+            state.values.push(value);
+        } else {
+            // Keep the new value with a probability of size / count, in place of a random one.
+            let i = rand::thread_rng().gen_range(0, state.count);
+
+            if i < self.size as u64 {
+                state.values[i as usize] = value;
+            }
+        }
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot::new(self.state.lock().unwrap().values.clone())
+    }
+}
+
+/// A reservoir which keeps the last `size` values recorded.
+pub struct SlidingWindowReservoir {
+    size: usize,
+    values: Mutex<VecDeque<u64>>,
+}
+
+impl SlidingWindowReservoir {
+    pub fn new(size: usize) -> SlidingWindowReservoir {
+        SlidingWindowReservoir {
+            size: size,
+            values: Mutex::new(VecDeque::with_capacity(size)),
+        }
+    }
+}
+
+impl Reservoir for SlidingWindowReservoir {
+    fn size(&self) -> usize {
+        self.values.lock().unwrap().len()
+    }
+
+    fn update(&self, value: u64) {
+        let mut values = self.values.lock().unwrap();
+
+        if values.len() >= self.size {
+            values.pop_front();
+        }
+        values.push_back(value);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot::new(self.values.lock().unwrap().iter().cloned().collect())
+    }
+}
+
+const DEFAULT_WINDOW_MAX_SIZE: usize = 1 << 16;
+
+/// A reservoir which keeps exactly the values recorded within a sliding window of time.
+///
+/// Expired values are trimmed lazily, whenever the reservoir is updated or read. To bound its
+/// memory the reservoir never holds more than a maximum number of values; once full, the oldest
+/// values are dropped first.
+pub struct SlidingTimeWindowReservoir<C: Clock = SystemClock> {
+    window: i64,
+    max_size: usize,
+    clock: C,
+    // Pairs of the time a value was recorded at and the value, oldest first.
+    values: Mutex<VecDeque<(i64, u64)>>,
+}
+
+impl SlidingTimeWindowReservoir<SystemClock> {
+    /// Creates a new reservoir which keeps the values of the given window, up to 65536 of them.
+    pub fn new(window: Duration) -> SlidingTimeWindowReservoir {
+        SlidingTimeWindowReservoir::with_max_size(window, DEFAULT_WINDOW_MAX_SIZE)
+    }
+
+    /// Creates a new reservoir which keeps the values of the given window, up to `max_size` of
+    /// them.
+    pub fn with_max_size(window: Duration, max_size: usize) -> SlidingTimeWindowReservoir {
+        SlidingTimeWindowReservoir::with_clock(window, max_size, SystemClock)
+    }
+}
+
+impl<C: Clock> SlidingTimeWindowReservoir<C> {
+    /// Creates a new reservoir which keeps the values of the given window, up to `max_size` of
+    /// them, and reads the time from the given clock.
+    pub fn with_clock(window: Duration,
+                      max_size: usize,
+                      clock: C)
+                      -> SlidingTimeWindowReservoir<C> {
+        SlidingTimeWindowReservoir {
+            window: meter::to_nanos(window),
+            max_size: max_size,
+            clock: clock,
+            values: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn trim(&self, values: &mut VecDeque<(i64, u64)>, now: i64) {
+        while values.front().map_or(false, |&(timestamp, _)| timestamp <= now - self.window) {
+            values.pop_front();
+        }
+    }
+}
+
+impl<C: Clock> Reservoir for SlidingTimeWindowReservoir<C> {
+    fn size(&self) -> usize {
+        let now = self.clock.now();
+        let mut values = self.values.lock().unwrap();
+
+        self.trim(&mut values, now);
+        values.len()
+    }
+
+    fn update(&self, value: u64) {
+        let now = self.clock.now();
+        let mut values = self.values.lock().unwrap();
+
+        self.trim(&mut values, now);
+
+        if values.len() >= self.max_size {
+            values.pop_front();
+        }
+        values.push_back((now, value));
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let now = self.clock.now();
+        let mut values = self.values.lock().unwrap();
+
+        self.trim(&mut values, now);
+        HistogramSnapshot::new(values.iter().map(|&(_, value)| value).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use histogram::*;
+
+    use handle::HistogramHandle;
+    use meter::{ManualClock, NANOS_PER_SEC};
+    use metric::{Metric, MetricValue};
+    use registry::{Registry, StdRegistry};
+    use super::*;
+
+    #[test]
+    fn fewer_values_than_size() {
+        let r = ExpDecayReservoir::with_clock(100, 0.99, ManualClock::new());
+
+        for v in 0..10 {
+            r.update(v);
+        }
+
+        assert_eq!(10, r.size());
+        assert_eq!(10, r.snapshot().values().len());
+    }
+
+    #[test]
+    fn more_values_than_size() {
+        let r = ExpDecayReservoir::with_clock(100, 0.99, ManualClock::new());
+
+        for v in 0..1000 {
+            r.update(v);
+        }
+
+        assert_eq!(100, r.size());
+
+        let s = r.snapshot();
+        assert_eq!(100, s.values().len());
+        assert!(s.values().iter().all(|&v| v < 1000));
+    }
+
+    #[test]
+    fn biased_towards_recent_values() {
+        let r = ExpDecayReservoir::with_clock(100, DEFAULT_ALPHA, ManualClock::new());
+
+        for _ in 0..1000 {
+            r.update(10);
+        }
+
+        r.clock.set(10 * 60 * NANOS_PER_SEC);
+
+        for _ in 0..1000 {
+            r.update(20);
+        }
+
+        let s = r.snapshot();
+        assert_eq!(20, s.percentile(50.0));
+        assert!(s.mean() > 19.9);
+    }
+
+    #[test]
+    fn rescale_keeps_relative_weights() {
+        let r = ExpDecayReservoir::with_clock(100, DEFAULT_ALPHA, ManualClock::new());
+
+        r.update(10);
+        r.clock.set(60 * NANOS_PER_SEC);
+        r.update(20);
+
+        let before = r.snapshot().mean();
+
+        r.clock.set(RESCALE_THRESHOLD);
+        r.rescale(&mut r.state.lock().unwrap(), RESCALE_THRESHOLD);
+
+        assert_eq!(RESCALE_THRESHOLD, r.state.lock().unwrap().start_time);
+        assert_eq!(2 * RESCALE_THRESHOLD, r.state.lock().unwrap().next_scale_time);
+        assert!((before - r.snapshot().mean()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rescale_on_update() {
+        let r = ExpDecayReservoir::with_clock(100, DEFAULT_ALPHA, ManualClock::new());
+
+        r.update(10);
+        r.clock.set(RESCALE_THRESHOLD + NANOS_PER_SEC);
+        r.update(20);
+
+        assert_eq!(RESCALE_THRESHOLD + NANOS_PER_SEC, r.state.lock().unwrap().start_time);
+        assert_eq!(2, r.size());
+    }
+
+    #[test]
+    fn histogram() {
+        let r = ExpDecayReservoir::with_clock(2, DEFAULT_ALPHA, ManualClock::new());
+        let h = ReservoirHistogram::new(r);
+
+        for v in 1..5 {
+            h.update(v);
+        }
+
+        match h.export_metric() {
+            MetricValue::Histogram(s) => {
+                assert_eq!(4, s.count());
+                assert_eq!(10, s.sum());
+                assert_eq!(2, s.values().len());
+            }
+            _ => panic!("expected a histogram"),
+        }
+    }
+
+    #[test]
+    fn hdr() {
+        let r = HdrReservoir::new(Histogram::new(HistogramConfig {
+                                                     max_memory: 0,
+                                                     max_value: 1000,
+                                                     precision: 3,
+                                                 })
+                                      .unwrap());
+
+        r.update(10);
+        r.update(20);
+        r.update(1000000);
+
+        assert_eq!(2, r.size());
+        assert_eq!(20, r.snapshot().max());
+        assert_eq!(30, r.snapshot().sum());
+    }
+
+    #[test]
+    fn hdr_exact_sum() {
+        let r = HdrReservoir::new(Histogram::new(HistogramConfig {
+                                                     max_memory: 0,
+                                                     max_value: 1000000,
+                                                     precision: 3,
+                                                 })
+                                      .unwrap());
+
+        // A heavy tail, which the sampled percentile curve misses.
+        for _ in 0..9999 {
+            r.update(1);
+        }
+        r.record(1000000, 1).unwrap();
+        assert!(r.record(2000000, 1).is_err());
+
+        let s = r.snapshot();
+        assert_eq!(10000, s.count());
+        assert_eq!(1009999, s.sum());
+        assert_eq!(s.sum() as f64 / s.count() as f64, s.mean());
+    }
+
+    #[test]
+    fn hdr_paths_agree() {
+        let histogram = Histogram::new(HistogramConfig {
+                                           max_memory: 0,
+                                           max_value: 1000,
+                                           precision: 3,
+                                       })
+                            .unwrap();
+        let h = ReservoirHistogram::new(HdrReservoir::new(histogram.clone()));
+        let handle = HistogramHandle::new(histogram);
+
+        for &v in [10, 20, 1000000].iter() {
+            h.update(v);
+            let _ = handle.record(v, 1);
+        }
+
+        for s in vec![h.snapshot(), handle.snapshot()] {
+            assert_eq!(2, s.count());
+            assert_eq!(30, s.sum());
+            assert_eq!(20, s.max());
+        }
+    }
+
+    #[test]
+    fn sliding_time_window_trims_expired() {
+        let r = SlidingTimeWindowReservoir::with_clock(Duration::from_secs(10),
+                                                       100,
+                                                       ManualClock::new());
+
+        r.update(1);
+        r.clock.set(5 * NANOS_PER_SEC);
+        r.update(2);
+        r.clock.set(9 * NANOS_PER_SEC);
+        r.update(3);
+
+        assert_eq!(vec![1, 2, 3], r.snapshot().values());
+
+        r.clock.set(10 * NANOS_PER_SEC);
+        assert_eq!(vec![2, 3], r.snapshot().values());
+
+        r.clock.set(15 * NANOS_PER_SEC);
+        assert_eq!(1, r.size());
+
+        r.clock.set(100 * NANOS_PER_SEC);
+        assert_eq!(0, r.size());
+        assert_eq!(0, r.snapshot().max());
+    }
+
+    #[test]
+    fn sliding_time_window_exact_percentiles() {
+        let r = SlidingTimeWindowReservoir::with_clock(Duration::from_secs(60),
+                                                       1000,
+                                                       ManualClock::new());
+
+        for t in 0..100 {
+            r.clock.set(t * NANOS_PER_SEC);
+            r.update(1000);
+        }
+
+        r.clock.set(100 * NANOS_PER_SEC);
+        for v in 1..101 {
+            r.update(v);
+        }
+
+        let s = r.snapshot();
+        assert_eq!(100 + 59, s.values().len());
+        assert_eq!(1000, s.max());
+
+        r.clock.set(159 * NANOS_PER_SEC);
+
+        let s = r.snapshot();
+        assert_eq!(100, s.values().len());
+        assert_eq!(50, s.percentile(50.0));
+        assert_eq!(99, s.percentile(99.0));
+    }
+
+    #[test]
+    fn sliding_time_window_bounded() {
+        let r = SlidingTimeWindowReservoir::with_clock(Duration::from_secs(60),
+                                                       10,
+                                                       ManualClock::new());
+
+        for v in 0..100 {
+            r.update(v);
+        }
+
+        assert_eq!(10, r.size());
+        assert_eq!(90, r.snapshot().min());
+    }
+
+    #[test]
+    fn uniform_fewer_values_than_size() {
+        let r = UniformReservoir::with_size(100);
+
+        for v in 0..10 {
+            r.update(v);
+        }
+
+        assert_eq!(10, r.size());
+        assert_eq!((0..10).collect::<Vec<_>>(), r.snapshot().values());
+    }
+
+    #[test]
+    fn uniform_matches_distribution() {
+        let r = UniformReservoir::with_size(1000);
+
+        // Every value from 0 to 99999 exactly once, in a scrambled order.
+        for i in 0..100000u64 {
+            r.update(i * 7919 % 100000);
+        }
+
+        assert_eq!(1000, r.size());
+
+        let s = r.snapshot();
+        for &p in [10.0, 25.0, 50.0, 75.0, 90.0].iter() {
+            let expected = p * 1000.0;
+            let actual = s.percentile(p) as f64;
+
+            assert!((actual - expected).abs() < 6000.0, "p{}: {} != {}", p, actual, expected);
+        }
+        assert!((s.mean() - 50000.0).abs() < 4000.0);
+    }
+
+    #[test]
+    fn uniform_is_not_biased_towards_recent_values() {
+        let r = UniformReservoir::with_size(1000);
+
+        for i in 0..100000u64 {
+            r.update(if i < 50000 { 0 } else { 1 });
+        }
+
+        let ones = r.snapshot().values().iter().filter(|&&v| v == 1).count();
+        assert!(ones > 400 && ones < 600, "{} of 1000 values are recent", ones);
+    }
+
+    #[test]
+    fn sliding_window_keeps_last_values() {
+        let r = SlidingWindowReservoir::new(3);
+
+        for v in 0..10 {
+            r.update(v);
+        }
+
+        assert_eq!(3, r.size());
+        assert_eq!(vec![7, 8, 9], r.snapshot().values());
+    }
+
+    #[test]
+    fn sliding_window_matches_distribution() {
+        let r = SlidingWindowReservoir::new(100);
+
+        for _ in 0..1000 {
+            r.update(1000000);
+        }
+        for i in 0..100u64 {
+            r.update(i * 37 % 100 + 1);
+        }
+
+        let s = r.snapshot();
+        assert_eq!(1, s.min());
+        assert_eq!(100, s.max());
+        assert_eq!(50, s.percentile(50.0));
+        assert_eq!(95, s.percentile(95.0));
+        assert!((s.mean() - 50.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reservoir_per_metric() {
+        let uniform = ReservoirHistogram::new(UniformReservoir::new());
+        let window = ReservoirHistogram::new(SlidingWindowReservoir::new(10));
+        let decaying = ReservoirHistogram::new(ExpDecayReservoir::new());
+
+        for v in 0..100 {
+            uniform.update(v);
+            window.update(v);
+            decaying.update(v);
+        }
+
+        let mut r = StdRegistry::new();
+        r.insert("uniform", uniform);
+        r.insert("window", window);
+        r.insert("decaying", decaying);
+
+        match r.get("window").export_metric() {
+            MetricValue::Histogram(s) => {
+                assert_eq!(100, s.count());
+                assert_eq!(90, s.min());
+            }
+            _ => panic!("expected a histogram"),
+        }
+        match r.get("uniform").export_metric() {
+            MetricValue::Histogram(s) => assert_eq!(100, s.values().len()),
+            _ => panic!("expected a histogram"),
+        }
+    }
+}
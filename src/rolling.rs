@@ -0,0 +1,302 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use meter::{self, Clock, SystemClock, NANOS_PER_SEC};
+use metric::{Metric, MetricValue};
+
+/// The counts of a `RollingCounter`, from the oldest bucket to the current one.
+#[derive(Clone, Debug)]
+pub struct RollingSnapshot {
+    pub buckets: Vec<u64>,
+    pub bucket_width: Duration,
+}
+
+impl RollingSnapshot {
+    /// Returns the total count over the window.
+    pub fn sum(&self) -> u64 {
+        self.buckets.iter().fold(0, |sum, &count| sum + count)
+    }
+
+    /// Returns the duration covered by every bucket together.
+    pub fn window(&self) -> Duration {
+        self.bucket_width * self.buckets.len() as u32
+    }
+
+    /// Returns the count per second over the window.
+    pub fn rate(&self) -> f64 {
+        let window = meter::to_nanos(self.window()) as f64 / NANOS_PER_SEC as f64;
+        self.sum() as f64 / window
+    }
+}
+
+struct Ring {
+    buckets: Vec<u64>,
+    // The index of the current bucket.
+    head: usize,
+    // When the current bucket started, in nanoseconds.
+    started: i64,
+}
+
+/// A counter which reports the exact count over a sliding window, such as the number of requests
+/// in the last 60 seconds.
+///
+/// The window is a ring of fixed-width buckets. Counts go into the current bucket, and the oldest
+/// bucket is cleared and becomes the current one whenever the clock moves past its width. As the
+/// current bucket is still filling, the window covers between `window - bucket_width` and
+/// `window` of history.
+pub struct RollingCounter<C: Clock = SystemClock> {
+    // The width of a bucket in nanoseconds.
+    bucket_width: i64,
+    ring: Mutex<Ring>,
+    clock: C,
+}
+
+impl RollingCounter<SystemClock> {
+    /// Creates a new counter over the given window, with a bucket per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the window is shorter than a second.
+    pub fn new(window: Duration) -> RollingCounter {
+        RollingCounter::with_bucket_width(window, Duration::from_secs(1))
+    }
+
+    /// Creates a new counter over the given window, divided into buckets of the given width.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket width is zero or longer than the window.
+    pub fn with_bucket_width(window: Duration, bucket_width: Duration) -> RollingCounter {
+        RollingCounter::with_clock(window, bucket_width, SystemClock)
+    }
+}
+
+impl<C: Clock> RollingCounter<C> {
+    /// Creates a new counter over the given window, divided into buckets of the given width,
+    /// which reads the time from the given clock.
+    ///
+    /// The window is rounded down to a whole number of buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket width is zero or longer than the window.
+    pub fn with_clock(window: Duration, bucket_width: Duration, clock: C) -> RollingCounter<C> {
+        let bucket_width = meter::to_nanos(bucket_width);
+        assert!(bucket_width > 0);
+
+        let len = (meter::to_nanos(window) / bucket_width) as usize;
+        assert!(len > 0);
+
+        let now = clock.now();
+
+        RollingCounter {
+            bucket_width: bucket_width,
+            ring: Mutex::new(Ring {
+                buckets: vec![0; len],
+                head: 0,
+                started: now,
+            }),
+            clock: clock,
+        }
+    }
+
+    // Clears the oldest bucket once per elapsed bucket width.
+    fn rotate(&self, ring: &mut Ring) {
+        let now = self.clock.now();
+
+        // A clock stepping backwards restarts the current bucket, without losing its count.
+        if now < ring.started {
+            ring.started = now;
+            return;
+        }
+
+        let elapsed = (now - ring.started) / self.bucket_width;
+        let len = ring.buckets.len();
+
+        for _ in 0..(elapsed as u64).min(len as u64) {
+            ring.head = (ring.head + 1) % len;
+            ring.buckets[ring.head] = 0;
+        }
+
+        ring.started += elapsed * self.bucket_width;
+    }
+
+    pub fn inc(&self, value: u64) {
+        let mut ring = self.ring.lock().unwrap();
+        self.rotate(&mut ring);
+
+        let head = ring.head;
+        ring.buckets[head] += value;
+    }
+
+    pub fn mark(&self) {
+        self.inc(1);
+    }
+
+    /// Returns the total count over the window.
+    pub fn sum(&self) -> u64 {
+        self.snapshot().sum()
+    }
+
+    /// Returns the count per second over the window.
+    pub fn rate(&self) -> f64 {
+        self.snapshot().rate()
+    }
+
+    pub fn snapshot(&self) -> RollingSnapshot {
+        let mut ring = self.ring.lock().unwrap();
+        self.rotate(&mut ring);
+
+        let len = ring.buckets.len();
+        let oldest = (ring.head + 1) % len;
+
+        RollingSnapshot {
+            buckets: (0..len).map(|i| ring.buckets[(oldest + i) % len]).collect(),
+            bucket_width: Duration::new((self.bucket_width / NANOS_PER_SEC) as u64,
+                                        (self.bucket_width % NANOS_PER_SEC) as u32),
+        }
+    }
+}
+
+impl<C: Clock> Metric for RollingCounter<C> {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Rolling(self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use meter::ManualClock;
+    use metric::{Metric, MetricValue};
+    use super::*;
+
+    fn counter(window: u64, clock: &ManualClock) -> RollingCounter<ManualClock> {
+        RollingCounter::with_clock(Duration::from_secs(window),
+                                   Duration::from_secs(1),
+                                   clock.clone())
+    }
+
+    #[test]
+    fn empty() {
+        let c = RollingCounter::new(Duration::from_secs(60));
+        let s = c.snapshot();
+
+        assert_eq!(60, s.buckets.len());
+        assert_eq!(Duration::from_secs(60), s.window());
+        assert_eq!(0, s.sum());
+        assert_eq!(0.0, s.rate());
+    }
+
+    #[test]
+    fn rotates_buckets() {
+        let clock = ManualClock::new();
+        let c = counter(3, &clock);
+
+        c.inc(1);
+        clock.advance(Duration::from_secs(1));
+        c.inc(2);
+        c.inc(2);
+        clock.advance(Duration::from_secs(1));
+        c.inc(3);
+
+        assert_eq!(vec![1, 4, 3], c.snapshot().buckets);
+        assert_eq!(8, c.sum());
+
+        // The oldest bucket falls out of the window.
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(vec![4, 3, 0], c.snapshot().buckets);
+        assert_eq!(7, c.sum());
+        assert_eq!(7.0 / 3.0, c.rate());
+    }
+
+    #[test]
+    fn partial_buckets() {
+        let clock = ManualClock::new();
+        let c = counter(2, &clock);
+
+        c.mark();
+        clock.advance(Duration::from_millis(1500));
+        c.mark();
+        clock.advance(Duration::from_millis(400));
+        c.mark();
+
+        assert_eq!(vec![1, 2], c.snapshot().buckets);
+    }
+
+    #[test]
+    fn expires_whole_window() {
+        let clock = ManualClock::new();
+        let c = counter(60, &clock);
+
+        c.inc(100);
+        clock.advance(Duration::from_secs(3600));
+
+        assert_eq!(0, c.sum());
+
+        c.mark();
+
+        assert_eq!(1, c.snapshot().buckets[59]);
+    }
+
+    #[test]
+    fn clock_steps_backwards() {
+        let clock = ManualClock::new();
+        clock.set(10 * NANOS_PER_SEC);
+        let c = counter(3, &clock);
+
+        c.inc(5);
+        clock.set(0);
+        c.inc(5);
+
+        assert_eq!(vec![0, 0, 10], c.snapshot().buckets);
+
+        // The current bucket restarted when the clock stepped back.
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(vec![0, 10, 0], c.snapshot().buckets);
+    }
+
+    #[test]
+    fn custom_bucket_width() {
+        let clock = ManualClock::new();
+        let c = RollingCounter::with_clock(Duration::from_secs(1),
+                                           Duration::from_millis(100),
+                                           clock.clone());
+
+        for _ in 0..10 {
+            c.mark();
+            clock.advance(Duration::from_millis(100));
+        }
+        c.mark();
+
+        let s = c.snapshot();
+        assert_eq!(10, s.buckets.len());
+        assert_eq!(Duration::from_millis(100), s.bucket_width);
+        assert_eq!(10, s.sum());
+        assert_eq!(10.0, s.rate());
+    }
+
+    #[test]
+    #[should_panic]
+    fn window_shorter_than_bucket() {
+        RollingCounter::with_bucket_width(Duration::from_millis(500), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn export() {
+        let clock = ManualClock::new();
+        let c = counter(10, &clock);
+        c.inc(20);
+
+        match c.export_metric() {
+            MetricValue::Rolling(s) => {
+                assert_eq!(20, s.sum());
+                assert_eq!(2.0, s.rate());
+            }
+            _ => panic!("expected a rolling counter"),
+        }
+    }
+}
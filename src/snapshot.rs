@@ -0,0 +1,395 @@
+use std::fmt;
+
+use histogram::Histogram;
+
+/// The percentiles reported for every histogram, along with the names reporters use for them.
+pub const PERCENTILES: [(f64, &'static str); 8] = [(50.0, "p50"),
+                                                   (75.0, "p75"),
+                                                   (95.0, "p95"),
+                                                   (98.0, "p98"),
+                                                   (99.0, "p99"),
+                                                   (99.9, "p999"),
+                                                   (99.99, "p9999"),
+                                                   (99.999, "p99999")];
+
+/// Calls `f` with every value that was missed while recording `value`, if a value was expected
+/// every `expected_interval`.
+///
+/// A measurement which takes much longer than the expected interval between measurements, such as
+/// a stall in a load generator, hides the measurements which should have started during it. They
+/// would have seen decreasing values: `value - expected_interval`, `value - 2 * expected_interval`
+/// and so on, down to the expected interval. Recording them as well corrects this coordinated
+/// omission, like HdrHistogram does. Nothing is missed if the expected interval is zero.
+pub fn missed_values<F>(value: u64, expected_interval: u64, mut f: F)
+    where F: FnMut(u64)
+{
+    if expected_interval == 0 {
+        return;
+    }
+
+    let mut missed = value.saturating_sub(expected_interval);
+    while missed >= expected_interval {
+        f(missed);
+        missed -= expected_interval;
+    }
+}
+
+/// A statistical snapshot of a histogram.
+///
+/// The snapshot keeps a sorted list of weighted sample values, from which arbitrary percentiles are
+/// computed. Count and sum cover every recorded value, even when the samples are only a subset of
+/// them, and once they're set with `with_totals`, the mean follows from them and the standard
+/// deviation is centered on it, so that all of them agree.
+#[derive(Clone, Debug)]
+pub struct HistogramSnapshot {
+    values: Vec<u64>,
+    // Normalized weight of each value.
+    weights: Vec<f64>,
+    // Cumulative normalized weight up to and including each value.
+    quantiles: Vec<f64>,
+    count: u64,
+    sum: u64,
+    // Whether the count and the sum were set by their source rather than derived from the samples.
+    totals: bool,
+}
+
+impl HistogramSnapshot {
+    /// Creates a snapshot in which every value has the same weight.
+    pub fn new(values: Vec<u64>) -> HistogramSnapshot {
+        HistogramSnapshot::weighted(values.into_iter().map(|v| (v, 1.0)).collect())
+    }
+
+    /// Creates a snapshot from values paired with their weights.
+    pub fn weighted(mut samples: Vec<(u64, f64)>) -> HistogramSnapshot {
+        samples.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let total = samples.iter().fold(0.0, |total, &(_, w)| total + w);
+        let mut cumulative = 0.0;
+
+        let mut values = Vec::with_capacity(samples.len());
+        let mut weights = Vec::with_capacity(samples.len());
+        let mut quantiles = Vec::with_capacity(samples.len());
+
+        for (value, weight) in samples {
+            let weight = if total > 0.0 { weight / total } else { 0.0 };
+            cumulative += weight;
+
+            values.push(value);
+            weights.push(weight);
+            quantiles.push(cumulative);
+        }
+
+        let mut snapshot = HistogramSnapshot {
+            count: values.len() as u64,
+            sum: 0,
+            values: values,
+            weights: weights,
+            quantiles: quantiles,
+            totals: false,
+        };
+        snapshot.sum = (snapshot.sampled_mean() * snapshot.count as f64).round() as u64;
+        snapshot
+    }
+
+    /// Summarizes an HDR histogram without copying it.
+    ///
+    /// The histogram doesn't track a running sum, so its mean, standard deviation and sum are
+    /// estimated from its sampled percentile curve, as described for `from_percentiles`. The
+    /// estimated sum can be far off for heavy-tailed values; `HdrReservoir` and `HistogramHandle`
+    /// track the sum alongside the histogram and replace it with the exact one.
+    pub fn from_histogram(histogram: &mut Histogram) -> HistogramSnapshot {
+        let count = histogram.count();
+
+        if count == 0 {
+            return HistogramSnapshot::new(Vec::new());
+        }
+
+        let snapshot = HistogramSnapshot::from_percentiles(|p| histogram.percentile(p).unwrap_or(0));
+        let sum = (snapshot.sampled_mean() * count as f64).round() as u64;
+
+        snapshot.with_totals(count, sum)
+    }
+
+    /// Creates a snapshot by sampling a percentile curve, given as a function from a percentile
+    /// between 0 and 100 to its value.
+    ///
+    /// The curve is sampled at every whole percent, and more densely towards the tail. Reported
+    /// percentiles are exact at these points. The count of the resulting snapshot is the number
+    /// of sampled points, so callers should set the real totals with `with_totals`.
+    pub fn from_percentiles<F>(mut percentile: F) -> HistogramSnapshot
+        where F: FnMut(f64) -> u64
+    {
+        // Percentile ranks in thousandths of a percent.
+        let mut ranks: Vec<u32> = (0..101).map(|p| p * 1000).collect();
+        ranks.extend((1..10).map(|i| 99000 + i * 100));
+        ranks.extend((1..10).map(|i| 99900 + i * 10));
+        ranks.extend((1..10).map(|i| 99990 + i));
+        ranks.sort();
+
+        let values: Vec<u64> = ranks.iter().map(|&r| percentile(r as f64 / 1000.0)).collect();
+
+        // Each point stands for half of the rank interval on either side of it. This keeps the
+        // cumulative weight of every point centered between its neighbours, so that looking up a
+        // sampled percentile lands exactly on it.
+        let samples = (0..ranks.len())
+                          .map(|i| {
+                              let below = if i > 0 { ranks[i] - ranks[i - 1] } else { 0 };
+                              let above = if i + 1 < ranks.len() { ranks[i + 1] - ranks[i] } else { 0 };
+
+                              (values[i], (below + above) as f64 / 2.0)
+                          })
+                          .collect();
+
+        HistogramSnapshot::weighted(samples)
+    }
+
+    /// Replaces the count and the sum, for sources which track them exactly.
+    pub fn with_totals(mut self, count: u64, sum: u64) -> HistogramSnapshot {
+        self.count = count;
+        self.sum = sum;
+        self.totals = true;
+        self
+    }
+
+    /// Returns a snapshot corrected for coordinated omission, as if each value had been recorded
+    /// along with the values it missed given the expected interval. See `missed_values`.
+    ///
+    /// This is meant for snapshots of values which were recorded without correction. The count
+    /// and the sum grow in proportion to the added values.
+    pub fn corrected(&self, expected_interval: u64) -> HistogramSnapshot {
+        let mut samples = Vec::with_capacity(self.values.len());
+
+        for (&value, &weight) in self.values.iter().zip(&self.weights) {
+            samples.push((value, weight));
+            missed_values(value, expected_interval, |missed| samples.push((missed, weight)));
+        }
+
+        let total = samples.iter().fold(0.0, |total, &(_, w)| total + w);
+        let count = (self.count as f64 * total).round() as u64;
+
+        let snapshot = HistogramSnapshot::weighted(samples);
+        let sum = (snapshot.sampled_mean() * count as f64).round() as u64;
+
+        snapshot.with_totals(count, sum)
+    }
+
+    /// Returns the number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of all recorded values.
+    ///
+    /// It's exact for sources which track a running sum, and otherwise estimated from the sampled
+    /// values, such as for snapshots of a bare `histogram::Histogram`.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    pub fn min(&self) -> u64 {
+        self.values.first().cloned().unwrap_or(0)
+    }
+
+    pub fn max(&self) -> u64 {
+        self.values.last().cloned().unwrap_or(0)
+    }
+
+    /// Returns the mean, which is `sum / count` when the totals were set by `with_totals`.
+    pub fn mean(&self) -> f64 {
+        if self.totals && self.count > 0 {
+            return self.sum as f64 / self.count as f64;
+        }
+
+        self.sampled_mean()
+    }
+
+    fn sampled_mean(&self) -> f64 {
+        self.values.iter().zip(&self.weights).fold(0.0, |mean, (&v, &w)| mean + v as f64 * w)
+    }
+
+    pub fn stddev(&self) -> f64 {
+        if self.values.len() <= 1 {
+            return 0.0;
+        }
+
+        let mean = self.mean();
+        let variance = self.values.iter().zip(&self.weights).fold(0.0, |variance, (&v, &w)| {
+            let diff = v as f64 - mean;
+            variance + w * diff * diff
+        });
+
+        variance.sqrt()
+    }
+
+    /// Returns the value at the given percentile, from 0 to 100.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.values.is_empty() {
+            return 0;
+        }
+
+        let quantile = percentile / 100.0 - 1e-9;
+
+        match self.quantiles.iter().position(|&q| q >= quantile) {
+            Some(i) => self.values[i],
+            None => self.max(),
+        }
+    }
+
+    /// Returns the sampled values in ascending order.
+    pub fn values(&self) -> &[u64] {
+        &self.values
+    }
+}
+
+impl fmt::Display for HistogramSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f,
+                    "count: {}, sum: {}, min: {}, max: {}, mean: {}, stddev: {}",
+                    self.count(),
+                    self.sum(),
+                    self.min(),
+                    self.max(),
+                    self.mean(),
+                    self.stddev()));
+
+        for &(percentile, name) in PERCENTILES.iter() {
+            try!(write!(f, ", {}: {}", name, self.percentile(percentile)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use histogram::*;
+
+    use super::*;
+
+    macro_rules! assert_float_eq {
+        ($x:expr, $y:expr, $d:expr) => {
+            if ($x - $y).abs() > $d { panic!("{} != {}", $x, $y); }
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let s = HistogramSnapshot::new(Vec::new());
+
+        assert_eq!(0, s.count());
+        assert_eq!(0, s.sum());
+        assert_eq!(0, s.min());
+        assert_eq!(0, s.max());
+        assert_eq!(0, s.percentile(99.0));
+        assert_float_eq!(0.0, s.mean(), 1e-9);
+        assert_float_eq!(0.0, s.stddev(), 1e-9);
+    }
+
+    #[test]
+    fn uniform() {
+        let s = HistogramSnapshot::new((1..101).rev().collect());
+
+        assert_eq!(100, s.count());
+        assert_eq!(5050, s.sum());
+        assert_eq!(1, s.min());
+        assert_eq!(100, s.max());
+        assert_eq!(50, s.percentile(50.0));
+        assert_eq!(99, s.percentile(99.0));
+        assert_eq!(100, s.percentile(99.9));
+        assert_eq!(1, s.percentile(0.0));
+        assert_float_eq!(50.5, s.mean(), 1e-9);
+        assert_float_eq!(28.866, s.stddev(), 1e-3);
+    }
+
+    #[test]
+    fn weighted() {
+        let s = HistogramSnapshot::weighted(vec![(10, 3.0), (20, 1.0)]);
+
+        assert_eq!(10, s.percentile(75.0));
+        assert_eq!(20, s.percentile(76.0));
+        assert_float_eq!(12.5, s.mean(), 1e-9);
+    }
+
+    #[test]
+    fn with_totals() {
+        let s = HistogramSnapshot::new(vec![1, 2, 3]).with_totals(1000, 2000);
+
+        assert_eq!(1000, s.count());
+        assert_eq!(2000, s.sum());
+        assert_float_eq!(2.0, s.mean(), 1e-9);
+    }
+
+    #[test]
+    fn mean_follows_totals() {
+        let s = HistogramSnapshot::new(vec![1, 2, 3]).with_totals(4, 100);
+
+        assert_float_eq!(25.0, s.mean(), 1e-9);
+        // Centered on the mean of the totals rather than on the samples.
+        assert_float_eq!(((576.0 + 529.0 + 484.0) / 3.0f64).sqrt(), s.stddev(), 1e-9);
+    }
+
+    #[test]
+    fn from_histogram() {
+        let mut h = Histogram::new(HistogramConfig {
+                                       max_memory: 0,
+                                       max_value: 1000000,
+                                       precision: 3,
+                                   })
+                        .unwrap();
+
+        for v in 1..1001 {
+            h.record(v, 1).unwrap();
+        }
+
+        let s = HistogramSnapshot::from_histogram(&mut h);
+
+        assert_eq!(1000, s.count());
+        assert_eq!(1, s.min());
+        assert_eq!(1000, s.max());
+        for &(percentile, _) in PERCENTILES.iter() {
+            assert_eq!(h.percentile(percentile).unwrap(), s.percentile(percentile));
+        }
+        assert_float_eq!(500.5, s.mean(), 5.0);
+        assert_float_eq!(500500.0, s.sum() as f64, 5000.0);
+        assert_float_eq!(288.7, s.stddev(), 5.0);
+    }
+
+    #[test]
+    fn missed() {
+        let mut values = Vec::new();
+        missed_values(100, 30, |v| values.push(v));
+
+        assert_eq!(vec![70, 40], values);
+
+        missed_values(100, 0, |_| panic!("nothing should be missed"));
+        missed_values(20, 30, |_| panic!("nothing should be missed"));
+    }
+
+    #[test]
+    fn corrected() {
+        let s = HistogramSnapshot::new(vec![10, 10, 10, 40]).corrected(10);
+
+        assert_eq!(7, s.count());
+        assert_eq!(130, s.sum());
+        assert_eq!(vec![10, 10, 10, 10, 20, 30, 40], s.values().to_vec());
+        assert_eq!(20, s.percentile(70.0));
+        assert_eq!(40, s.max());
+    }
+
+    #[test]
+    fn corrected_keeps_totals_ratio() {
+        let s = HistogramSnapshot::new(vec![10, 30]).with_totals(1000, 20000).corrected(10);
+
+        assert_eq!(2000, s.count());
+        assert_eq!(35000, s.sum());
+    }
+
+    #[test]
+    fn display() {
+        let s = HistogramSnapshot::new(vec![5]);
+
+        assert_eq!("count: 1, sum: 5, min: 5, max: 5, mean: 5, stddev: 0, p50: 5, p75: 5, p95: 5, \
+                    p98: 5, p99: 5, p999: 5, p9999: 5, p99999: 5",
+                   s.to_string());
+    }
+}
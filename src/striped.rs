@@ -0,0 +1,204 @@
+use std::sync::atomic::AtomicUsize;
+
+use syncbox::atomic::{AtomicI64, Ordering};
+
+use counter::{Counter, StdCounter};
+use metric::{Metric, MetricValue};
+
+const DEFAULT_STRIPES: usize = 16;
+
+// Hands out a distinct stripe index to every thread that touches a striped counter.
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local!(static STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed));
+
+// A single slot of a striped counter.
+//
+// The padding keeps the values of neighbouring cells at least a cache line apart, so threads
+// writing to different cells never contend on the same line.
+struct Cell {
+    value: AtomicI64,
+    _pad: [u64; 7],
+}
+
+impl Cell {
+    fn new() -> Cell {
+        Cell { value: AtomicI64::new(0), _pad: [0; 7] }
+    }
+}
+
+/// A counter for hot paths which spreads its updates over a number of padded cells.
+///
+/// Each thread is assigned a cell of its own, so concurrent updates rarely touch the same cache
+/// line. Reading the value sums all cells, which makes reads more expensive than with a single
+/// atomic, but keeps writers from serializing on each other.
+pub struct StripedCounter {
+    cells: Vec<Cell>,
+    mask: usize,
+}
+
+impl StripedCounter {
+    pub fn new() -> StripedCounter {
+        StripedCounter::with_stripes(DEFAULT_STRIPES)
+    }
+
+    /// Creates a new counter with at least the given number of cells.
+    ///
+    /// The number of cells is rounded up to the next power of two.
+    pub fn with_stripes(stripes: usize) -> StripedCounter {
+        let stripes = stripes.next_power_of_two();
+
+        StripedCounter {
+            cells: (0..stripes).map(|_| Cell::new()).collect(),
+            mask: stripes - 1,
+        }
+    }
+
+    fn cell(&self) -> &Cell {
+        let stripe = STRIPE.with(|stripe| *stripe);
+        &self.cells[stripe & self.mask]
+    }
+
+    pub fn add(&self, value: i64) {
+        self.cell().value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for cell in &self.cells {
+            cell.value.store(0, Ordering::SeqCst);
+        }
+    }
+
+    pub fn dec(&self, value: i64) {
+        self.add(value.wrapping_neg());
+    }
+
+    pub fn inc(&self, value: i64) {
+        self.add(value);
+    }
+
+    /// Returns the sum of all cells.
+    ///
+    /// Updates which happen concurrently with the summation may or may not be included.
+    pub fn sum(&self) -> i64 {
+        self.cells.iter().fold(0, |sum, cell| sum + cell.value.load(Ordering::SeqCst))
+    }
+
+    /// Returns the sum of all cells and resets them to zero.
+    ///
+    /// Every update is accounted for exactly once, either by this call or by the next one.
+    pub fn sum_and_reset(&self) -> i64 {
+        self.cells.iter().fold(0, |sum, cell| sum + cell.value.swap(0, Ordering::SeqCst))
+    }
+}
+
+impl Counter for StripedCounter {
+    fn clear(&mut self) {
+        StripedCounter::clear(self);
+    }
+
+    fn dec(&mut self, value: i64) {
+        StripedCounter::dec(self, value);
+    }
+
+    fn inc(&mut self, value: i64) {
+        StripedCounter::inc(self, value);
+    }
+
+    fn snapshot(self) -> StripedCounter {
+        let counter = StripedCounter::with_stripes(self.cells.len());
+        counter.add(self.sum());
+        counter
+    }
+}
+
+impl Metric for StripedCounter {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Counter(StdCounter { value: self.sum() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+
+    use metric::{Metric, MetricValue};
+    use super::*;
+
+    #[test]
+    fn inc_dec_clear() {
+        let c = StripedCounter::new();
+        c.inc(5);
+        c.dec(2);
+
+        assert_eq!(3, c.sum());
+
+        c.clear();
+
+        assert_eq!(0, c.sum());
+    }
+
+    #[test]
+    fn stripes_are_rounded_up() {
+        let c = StripedCounter::with_stripes(5);
+
+        assert_eq!(8, c.cells.len());
+    }
+
+    #[test]
+    fn sum_from_threads() {
+        let c = Arc::new(StripedCounter::with_stripes(4));
+
+        let threads: Vec<_> = (0..16)
+                                  .map(|_| {
+                                      let c = c.clone();
+                                      thread::spawn(move || {
+                                          for _ in 0..1000 {
+                                              c.inc(1);
+                                          }
+                                      })
+                                  })
+                                  .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(16000, c.sum());
+    }
+
+    #[test]
+    fn sum_and_reset() {
+        let c = StripedCounter::new();
+        c.inc(10);
+
+        assert_eq!(10, c.sum_and_reset());
+        assert_eq!(0, c.sum());
+    }
+
+    #[test]
+    fn export() {
+        let c = StripedCounter::new();
+        c.inc(42);
+
+        match c.export_metric() {
+            MetricValue::Counter(x) => assert_eq!(42, x.value),
+            _ => panic!("expected a counter"),
+        }
+    }
+
+    #[test]
+    fn send() {
+        fn checker<T: Send>(_: T) {}
+
+        checker(StripedCounter::new());
+    }
+
+    #[test]
+    fn sync() {
+        fn checker<T: Sync>(_: T) {}
+
+        checker(StripedCounter::new());
+    }
+}
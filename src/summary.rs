@@ -0,0 +1,487 @@
+use std::cmp;
+use std::f64;
+use std::mem;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use meter::{self, Clock, SystemClock};
+use metric::{Metric, MetricValue};
+
+// Values are buffered and merged into the samples in sorted batches of this size.
+const BUFFER_SIZE: usize = 500;
+
+/// A quantile tracked by a `Summary`, with the error allowed on its rank.
+///
+/// An error of 0.01 for the 0.99 quantile means that the reported value has a rank between 98%
+/// and 100% of the observations.
+#[derive(Clone, Copy, Debug)]
+pub struct Quantile {
+    pub quantile: f64,
+    pub error: f64,
+}
+
+impl Quantile {
+    /// # Panics
+    ///
+    /// Panics if the quantile is not between 0 and 1 exclusive, or if the error is not between 0
+    /// and 1 exclusive.
+    pub fn new(quantile: f64, error: f64) -> Quantile {
+        assert!(quantile > 0.0 && quantile < 1.0);
+        assert!(error > 0.0 && error < 1.0);
+
+        Quantile {
+            quantile: quantile,
+            error: error,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    value: f64,
+    // The difference between the lowest possible rank of this sample and of the previous one.
+    width: u64,
+    // The difference between the highest and the lowest possible rank of this sample.
+    delta: u64,
+}
+
+// A CKMS stream, which keeps just enough samples to answer its target quantiles within their
+// errors.
+struct Stream {
+    samples: Vec<Sample>,
+    buffer: Vec<f64>,
+    count: u64,
+}
+
+impl Stream {
+    fn new() -> Stream {
+        Stream {
+            samples: Vec::new(),
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            count: 0,
+        }
+    }
+
+    fn insert(&mut self, value: f64, targets: &[Quantile]) {
+        self.buffer.push(value);
+
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.flush(targets);
+        }
+    }
+
+    fn flush(&mut self, targets: &[Quantile]) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let mut buffer = mem::replace(&mut self.buffer, Vec::with_capacity(BUFFER_SIZE));
+        buffer.sort_by(|a, b| a.partial_cmp(b).unwrap_or(cmp::Ordering::Equal));
+
+        let samples = mem::replace(&mut self.samples, Vec::new());
+        let mut merged = Vec::with_capacity(samples.len() + buffer.len());
+        let mut existing = samples.into_iter().peekable();
+        let mut rank = 0.0;
+
+        for value in buffer {
+            while existing.peek().map_or(false, |s| s.value <= value) {
+                let sample = existing.next().unwrap();
+                rank += sample.width as f64;
+                merged.push(sample);
+            }
+
+            self.count += 1;
+
+            // A new minimum or maximum is known exactly.
+            let delta = if merged.is_empty() || existing.peek().is_none() {
+                0
+            } else {
+                (invariant(targets, rank, self.count as f64).floor() as u64).saturating_sub(1)
+            };
+
+            merged.push(Sample {
+                value: value,
+                width: 1,
+                delta: delta,
+            });
+            rank += 1.0;
+        }
+        merged.extend(existing);
+
+        self.samples = merged;
+        self.compress(targets);
+    }
+
+    // Merges every sample into its successor whenever the invariant allows it, leaving the
+    // minimum and the maximum in place.
+    fn compress(&mut self, targets: &[Quantile]) {
+        let mut samples = mem::replace(&mut self.samples, Vec::new());
+        let n = self.count as f64;
+
+        let mut next = match samples.pop() {
+            Some(next) => next,
+            None => return,
+        };
+        let mut rank = n - next.width as f64;
+        let mut compressed = Vec::with_capacity(samples.len() + 1);
+
+        while let Some(current) = samples.pop() {
+            rank -= current.width as f64;
+
+            if !samples.is_empty() &&
+               (current.width + next.width + next.delta) as f64 <= invariant(targets, rank, n) {
+                next.width += current.width;
+            } else {
+                compressed.push(next);
+                next = current;
+            }
+        }
+        compressed.push(next);
+        compressed.reverse();
+
+        self.samples = compressed;
+    }
+
+    fn query(&mut self, quantile: f64, targets: &[Quantile]) -> f64 {
+        self.flush(targets);
+
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+
+        let n = self.count as f64;
+        let desired = quantile * n;
+        let bound = desired + invariant(targets, desired, n) / 2.0;
+        let mut rank = 0.0;
+
+        for pair in self.samples.windows(2) {
+            rank += pair[0].width as f64;
+
+            if rank + (pair[1].width + pair[1].delta) as f64 > bound {
+                return pair[0].value;
+            }
+        }
+
+        self.samples[self.samples.len() - 1].value
+    }
+}
+
+// The largest allowed uncertainty on the rank of a sample of rank r, among n values.
+fn invariant(targets: &[Quantile], rank: f64, n: f64) -> f64 {
+    targets.iter().fold(f64::INFINITY, |min, target| {
+        let allowed = if rank >= target.quantile * n {
+            2.0 * target.error * rank / target.quantile
+        } else {
+            2.0 * target.error * (n - rank) / (1.0 - target.quantile)
+        };
+
+        min.min(allowed)
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct SummarySnapshot {
+    /// Pairs of target quantiles and their values.
+    pub quantiles: Vec<(f64, f64)>,
+    pub count: u64,
+    pub sum: f64
+}
+
+struct Window {
+    // One stream per age bucket, all of them receiving every value. The head is the oldest one.
+    streams: Vec<Stream>,
+    head: usize,
+    rotated: i64,
+    count: u64,
+    sum: f64,
+}
+
+/// A Summary tracks targeted quantiles of a stream of values, along with their count and sum.
+///
+/// It uses the CKMS algorithm, which keeps only as many samples as the target quantiles need for
+/// their errors, so tight errors on tail quantiles and loose ones elsewhere stay cheap.
+///
+/// Summaries created with a maximum age only report quantiles over the recent values. Their
+/// values are recorded into several streams which are reset one after the other, each one after
+/// the maximum age, so values leave the quantiles between `max_age - max_age / age_buckets` and
+/// `max_age` after being recorded. The count and the sum always cover every value.
+pub struct Summary<C: Clock = SystemClock> {
+    targets: Vec<Quantile>,
+    // The duration of an age bucket in nanoseconds, if values expire.
+    bucket_age: Option<i64>,
+    window: Mutex<Window>,
+    clock: C,
+}
+
+impl Summary<SystemClock> {
+    /// Creates a new summary tracking the given quantiles over every value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no target quantiles.
+    pub fn new(targets: Vec<Quantile>) -> Summary {
+        Summary::with(targets, None, 1, SystemClock)
+    }
+
+    /// Creates a new summary tracking the given quantiles over the values recorded within
+    /// max_age, which is divided into age_buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no target quantiles, if age_buckets is zero or if max_age is shorter
+    /// than a nanosecond per age bucket.
+    pub fn with_max_age(targets: Vec<Quantile>, max_age: Duration, age_buckets: usize) -> Summary {
+        Summary::with(targets, Some(max_age), age_buckets, SystemClock)
+    }
+}
+
+impl<C: Clock> Summary<C> {
+    /// Creates a new summary tracking the given quantiles over every value, which reads the time
+    /// from the given clock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no target quantiles.
+    pub fn with_clock(targets: Vec<Quantile>, clock: C) -> Summary<C> {
+        Summary::with(targets, None, 1, clock)
+    }
+
+    /// Creates a new summary tracking the given quantiles over the values recorded within
+    /// max_age, as measured by the given clock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no target quantiles, if age_buckets is zero or if max_age is shorter
+    /// than a nanosecond per age bucket.
+    pub fn with_max_age_and_clock(targets: Vec<Quantile>,
+                                  max_age: Duration,
+                                  age_buckets: usize,
+                                  clock: C)
+                                  -> Summary<C> {
+        Summary::with(targets, Some(max_age), age_buckets, clock)
+    }
+
+    fn with(targets: Vec<Quantile>,
+            max_age: Option<Duration>,
+            age_buckets: usize,
+            clock: C)
+            -> Summary<C> {
+        assert!(!targets.is_empty());
+        assert!(age_buckets > 0);
+
+        let bucket_age = max_age.map(|max_age| meter::to_nanos(max_age) / age_buckets as i64);
+        assert!(bucket_age.map_or(true, |age| age > 0));
+
+        let now = clock.now();
+
+        Summary {
+            targets: targets,
+            bucket_age: bucket_age,
+            window: Mutex::new(Window {
+                streams: (0..age_buckets).map(|_| Stream::new()).collect(),
+                head: 0,
+                rotated: now,
+                count: 0,
+                sum: 0.0,
+            }),
+            clock: clock,
+        }
+    }
+
+    // Resets the oldest stream once per elapsed age bucket.
+    fn rotate(&self, window: &mut Window) {
+        let bucket_age = match self.bucket_age {
+            Some(age) => age,
+            None => return,
+        };
+
+        let elapsed = self.clock.now().saturating_sub(window.rotated) / bucket_age;
+        if elapsed <= 0 {
+            return;
+        }
+
+        // Past a full rotation, every stream is reset anyway.
+        let len = window.streams.len();
+        for _ in 0..cmp::min(elapsed as u64, len as u64) {
+            window.streams[window.head] = Stream::new();
+            window.head = (window.head + 1) % len;
+        }
+
+        window.rotated += elapsed * bucket_age;
+    }
+
+    /// Records a value. NaN values are ignored.
+    pub fn observe(&self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+
+        let mut window = self.window.lock().unwrap();
+        self.rotate(&mut window);
+
+        window.count += 1;
+        window.sum += value;
+
+        for stream in &mut window.streams {
+            stream.insert(value, &self.targets);
+        }
+    }
+
+    pub fn snapshot(&self) -> SummarySnapshot {
+        let mut window = self.window.lock().unwrap();
+        self.rotate(&mut window);
+
+        let head = window.head;
+        let quantiles = self.targets
+                            .iter()
+                            .map(|t| (t.quantile, window.streams[head].query(t.quantile, &self.targets)))
+                            .collect();
+
+        SummarySnapshot {
+            quantiles: quantiles,
+            count: window.count,
+            sum: window.sum,
+        }
+    }
+}
+
+impl<C: Clock> Metric for Summary<C> {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Summary(self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use meter::{ManualClock, NANOS_PER_SEC};
+    use metric::{Metric, MetricValue};
+    use super::*;
+
+    fn targets() -> Vec<Quantile> {
+        vec![Quantile::new(0.5, 0.05), Quantile::new(0.9, 0.01), Quantile::new(0.99, 0.001)]
+    }
+
+    #[test]
+    fn empty() {
+        let s = Summary::new(targets()).snapshot();
+
+        assert_eq!(0, s.count);
+        assert_eq!(vec![(0.5, 0.0), (0.9, 0.0), (0.99, 0.0)], s.quantiles);
+    }
+
+    #[test]
+    fn targeted_errors() {
+        let summary = Summary::new(targets());
+        let n = 100000;
+
+        // Interleave the values so that they don't arrive sorted.
+        for i in 0..n {
+            summary.observe(((i * 7919) % n) as f64);
+        }
+
+        let s = summary.snapshot();
+
+        assert_eq!(n, s.count);
+        assert_eq!((n * (n - 1) / 2) as f64, s.sum);
+
+        for (&(quantile, value), target) in s.quantiles.iter().zip(targets()) {
+            let expected = quantile * n as f64;
+
+            assert!((value - expected).abs() <= target.error * n as f64,
+                    "q{}: {} != {}",
+                    quantile,
+                    value,
+                    expected);
+        }
+    }
+
+    #[test]
+    fn keeps_few_samples() {
+        let summary = Summary::new(targets());
+
+        for i in 0..100000 {
+            summary.observe(((i * 7919) % 100000) as f64);
+        }
+        summary.snapshot();
+
+        let window = summary.window.lock().unwrap();
+        assert!(window.streams[0].samples.len() < 2000,
+                "{} samples",
+                window.streams[0].samples.len());
+    }
+
+    #[test]
+    fn max_age() {
+        let summary = Summary::with(vec![Quantile::new(0.5, 0.01)],
+                                    Some(Duration::from_secs(60)),
+                                    3,
+                                    ManualClock::new());
+
+        for _ in 0..150 {
+            summary.observe(1.0);
+        }
+
+        summary.clock.set(30 * NANOS_PER_SEC);
+        for _ in 0..100 {
+            summary.observe(5.0);
+        }
+
+        // The first values are still within the window.
+        summary.clock.set(59 * NANOS_PER_SEC);
+        assert_eq!(vec![(0.5, 1.0)], summary.snapshot().quantiles);
+
+        // Only the later values are left.
+        summary.clock.set(60 * NANOS_PER_SEC);
+        let s = summary.snapshot();
+        assert_eq!(vec![(0.5, 5.0)], s.quantiles);
+        assert_eq!(250, s.count);
+        assert_eq!(650.0, s.sum);
+
+        // Every value expired.
+        summary.clock.set(1000 * NANOS_PER_SEC);
+        assert_eq!(vec![(0.5, 0.0)], summary.snapshot().quantiles);
+    }
+
+    #[test]
+    fn long_idle() {
+        let clock = ManualClock::new();
+        let summary = Summary::with_max_age_and_clock(vec![Quantile::new(0.5, 0.01)],
+                                                      Duration::from_millis(3),
+                                                      3,
+                                                      clock.clone());
+        summary.observe(1.0);
+
+        // A day of one millisecond buckets expires at once, and the buckets stay aligned.
+        clock.advance(Duration::from_secs(86400));
+        summary.observe(2.0);
+        assert_eq!(vec![(0.5, 2.0)], summary.snapshot().quantiles);
+
+        clock.advance(Duration::from_millis(2));
+        assert_eq!(vec![(0.5, 2.0)], summary.snapshot().quantiles);
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(vec![(0.5, 0.0)], summary.snapshot().quantiles);
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_quantile() {
+        Quantile::new(1.0, 0.01);
+    }
+
+    #[test]
+    fn export() {
+        let summary = Summary::new(targets());
+        summary.observe(3.0);
+
+        match summary.export_metric() {
+            MetricValue::Summary(s) => {
+                assert_eq!(1, s.count);
+                assert_eq!(vec![(0.5, 3.0), (0.9, 3.0), (0.99, 3.0)], s.quantiles);
+            }
+            _ => panic!("expected a summary"),
+        }
+    }
+}
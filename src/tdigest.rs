@@ -0,0 +1,406 @@
+use std::cmp::Ordering;
+use std::f64;
+use std::sync::Mutex;
+
+use metric::{Metric, MetricValue};
+use reservoir::Reservoir;
+use snapshot::HistogramSnapshot;
+
+const DEFAULT_COMPRESSION: f64 = 100.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// A t-digest, a sketch of a distribution which is most accurate at the extreme quantiles.
+///
+/// Values are summarized into centroids, weighted means of neighbouring values. A centroid may
+/// only grow while it spans a small enough range of quantiles, and that range shrinks towards both
+/// ends of the distribution, so the tails are kept at a much finer resolution than the median.
+/// The number of centroids is bounded by the compression, regardless of how many values are added.
+///
+/// Digests can be merged, which combines the values of both.
+#[derive(Clone, Debug)]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    // Values which haven't been merged into the centroids yet.
+    buffer: Vec<Centroid>,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    /// Creates a new digest with a compression of 100.
+    pub fn new() -> TDigest {
+        TDigest::with_compression(DEFAULT_COMPRESSION)
+    }
+
+    /// Creates a new digest with the given compression.
+    ///
+    /// A higher compression keeps more centroids, trading memory for accuracy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if compression < 1.
+    pub fn with_compression(compression: f64) -> TDigest {
+        assert!(compression >= 1.0);
+
+        TDigest {
+            compression: compression,
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Adds a value. Infinite and NaN values are ignored.
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        self.buffer.push(Centroid {
+            mean: value,
+            weight: 1.0,
+        });
+
+        if self.buffer.len() as f64 >= self.compression * 5.0 {
+            self.compress();
+        }
+    }
+
+    /// Adds every value of another digest.
+    pub fn merge(&mut self, other: &TDigest) {
+        if other.count == 0 {
+            return;
+        }
+
+        self.buffer.extend(other.centroids.iter().cloned());
+        self.buffer.extend(other.buffer.iter().cloned());
+
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+
+        self.compress();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Returns the smallest value, or zero if the digest is empty.
+    pub fn min(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.min }
+    }
+
+    /// Returns the largest value, or zero if the digest is empty.
+    pub fn max(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.max }
+    }
+
+    /// Returns the number of centroids, once every buffered value is merged.
+    pub fn centroids(&self) -> usize {
+        self.compressed().centroids.len()
+    }
+
+    /// Returns the estimated value at the given quantile, from 0 to 1.
+    ///
+    /// Returns zero if the digest is empty.
+    pub fn quantile(&self, quantile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        if !self.buffer.is_empty() {
+            return self.compressed().quantile(quantile);
+        }
+        if quantile <= 0.0 {
+            return self.min;
+        }
+        if quantile >= 1.0 {
+            return self.max;
+        }
+
+        let centroids = &self.centroids;
+        let total = self.count as f64;
+        let index = quantile * total;
+
+        // Every centroid is assumed to be centered on its mean, with half of its weight on either
+        // side. Quantiles between two centers are interpolated between both means, while those
+        // beyond the outer centers are interpolated towards the extremes.
+        let first = centroids[0];
+        if index < first.weight / 2.0 {
+            return interpolate(self.min, first.mean, index / (first.weight / 2.0));
+        }
+
+        let mut before = 0.0;
+        for pair in centroids.windows(2) {
+            let left = before + pair[0].weight / 2.0;
+            let right = before + pair[0].weight + pair[1].weight / 2.0;
+
+            if index < right {
+                return interpolate(pair[0].mean, pair[1].mean, (index - left) / (right - left));
+            }
+
+            before += pair[0].weight;
+        }
+
+        let last = centroids[centroids.len() - 1];
+        let left = total - last.weight / 2.0;
+        interpolate(last.mean, self.max, (index - left) / (last.weight / 2.0))
+    }
+
+    fn compressed(&self) -> TDigest {
+        let mut digest = self.clone();
+        digest.compress();
+        digest
+    }
+
+    // Merges the buffered values into the centroids.
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let mut all = Vec::with_capacity(self.centroids.len() + self.buffer.len());
+        all.extend(self.centroids.drain(..));
+        all.extend(self.buffer.drain(..));
+        all.sort_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap_or(Ordering::Equal));
+
+        let total = all.iter().fold(0.0, |total, c| total + c.weight);
+
+        // Each centroid may span one unit of the scale function
+        // k(q) = compression / z · ln(q / (1 - q)), where z = 4 ln(n / compression) + 24. The span
+        // of one unit shrinks geometrically towards both ends, keeping the tails at a resolution
+        // of single values while bounding the number of centroids.
+        let normalizer = 4.0 * (total / self.compression).max(1.0).ln() + 24.0;
+        let scale = self.compression / normalizer;
+
+        let limit = |quantile: f64| {
+            let k = scale * (quantile / (1.0 - quantile)).ln() + 1.0;
+            1.0 / (1.0 + (-k / scale).exp())
+        };
+
+        let mut merged = Vec::new();
+        let mut current = all[0];
+        let mut before = 0.0;
+        let mut upper = limit(0.0);
+
+        for &next in &all[1..] {
+            if (before + current.weight + next.weight) / total <= upper {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / weight;
+                current.weight = weight;
+            } else {
+                before += current.weight;
+                upper = limit(before / total);
+                merged.push(current);
+                current = next;
+            }
+        }
+        merged.push(current);
+
+        self.centroids = merged;
+    }
+}
+
+fn interpolate(from: f64, to: f64, fraction: f64) -> f64 {
+    from + (to - from) * fraction
+}
+
+/// A histogram backed by a `TDigest`.
+///
+/// It reports the same statistics as the other histograms, but keeps accurate tail percentiles
+/// over an unbounded number of values in a small, constant amount of memory. It can also be used
+/// as the reservoir of a timer.
+pub struct TDigestHistogram {
+    digest: Mutex<TDigest>,
+}
+
+impl TDigestHistogram {
+    /// Creates a new histogram with a compression of 100.
+    pub fn new() -> TDigestHistogram {
+        TDigestHistogram::with_compression(DEFAULT_COMPRESSION)
+    }
+
+    /// Creates a new histogram with the given compression.
+    ///
+    /// # Panics
+    ///
+    /// Panics if compression < 1.
+    pub fn with_compression(compression: f64) -> TDigestHistogram {
+        TDigestHistogram { digest: Mutex::new(TDigest::with_compression(compression)) }
+    }
+
+    pub fn update(&self, value: u64) {
+        self.digest.lock().unwrap().add(value as f64);
+    }
+
+    /// Adds every value of a digest, which may come from another histogram or process.
+    pub fn merge(&self, other: &TDigest) {
+        self.digest.lock().unwrap().merge(other);
+    }
+
+    /// Returns a copy of the digest.
+    pub fn digest(&self) -> TDigest {
+        self.digest.lock().unwrap().clone()
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let digest = self.digest().compressed();
+
+        if digest.count() == 0 {
+            return HistogramSnapshot::new(Vec::new());
+        }
+
+        HistogramSnapshot::from_percentiles(|p| digest.quantile(p / 100.0).max(0.0).round() as u64)
+            .with_totals(digest.count(), digest.sum().max(0.0).round() as u64)
+    }
+}
+
+impl Reservoir for TDigestHistogram {
+    fn size(&self) -> usize {
+        self.digest.lock().unwrap().count() as usize
+    }
+
+    fn update(&self, value: u64) {
+        TDigestHistogram::update(self, value)
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        TDigestHistogram::snapshot(self)
+    }
+}
+
+impl Metric for TDigestHistogram {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Histogram(self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use metric::{Metric, MetricValue};
+    use timer::Timer;
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let d = TDigest::new();
+
+        assert_eq!(0, d.count());
+        assert_eq!(0.0, d.quantile(0.5));
+        assert_eq!(0, TDigestHistogram::new().snapshot().count());
+    }
+
+    #[test]
+    fn single_value() {
+        let mut d = TDigest::new();
+        d.add(7.0);
+
+        assert_eq!(7.0, d.quantile(0.0));
+        assert_eq!(7.0, d.quantile(0.5));
+        assert_eq!(7.0, d.quantile(1.0));
+    }
+
+    #[test]
+    fn bounded_centroids() {
+        let mut d = TDigest::new();
+
+        for v in 0..100000 {
+            d.add(v as f64);
+        }
+
+        assert_eq!(100000, d.count());
+        assert!(d.centroids() <= 100, "{} centroids", d.centroids());
+    }
+
+    #[test]
+    fn accurate_tails() {
+        let mut d = TDigest::new();
+
+        // Interleave the values so that they don't arrive sorted.
+        for i in 0..100000u64 {
+            d.add(((i * 7919) % 100000) as f64);
+        }
+
+        assert_eq!(0.0, d.quantile(0.0));
+        assert_eq!(99999.0, d.quantile(1.0));
+
+        for &(q, error) in [(0.5, 1000.0), (0.99, 100.0), (0.999, 20.0), (0.9999, 5.0)].iter() {
+            let expected = q * 100000.0;
+            let actual = d.quantile(q);
+
+            assert!((actual - expected).abs() <= error, "q{}: {} != {}", q, actual, expected);
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = TDigest::new();
+        let mut b = TDigest::new();
+
+        for v in 0..50000 {
+            a.add(v as f64);
+            b.add((v + 50000) as f64);
+        }
+
+        a.merge(&b);
+
+        assert_eq!(100000, a.count());
+        assert_eq!(0.0, a.min());
+        assert_eq!(99999.0, a.max());
+        assert!((a.quantile(0.5) - 50000.0).abs() <= 1000.0);
+        assert!((a.quantile(0.999) - 99900.0).abs() <= 20.0);
+    }
+
+    #[test]
+    fn export() {
+        let h = TDigestHistogram::new();
+
+        for v in 1..1001 {
+            h.update(v);
+        }
+
+        match h.export_metric() {
+            MetricValue::Histogram(s) => {
+                assert_eq!(1000, s.count());
+                assert_eq!(500500, s.sum());
+                assert_eq!(1, s.min());
+                assert_eq!(1000, s.max());
+                assert!((s.percentile(99.0) as i64 - 990).abs() <= 2);
+            }
+            _ => panic!("expected a histogram"),
+        }
+    }
+
+    #[test]
+    fn timer_reservoir() {
+        let t = Timer::with_reservoir(TDigestHistogram::new());
+        t.update_ns(1000);
+        t.update_ns(3000);
+
+        let s = t.snapshot();
+
+        assert_eq!(2, s.histogram.count());
+        assert_eq!(3000, s.histogram.max());
+    }
+}
@@ -0,0 +1,336 @@
+use std::cmp;
+use std::time::Duration;
+
+use histogram::{Histogram, HistogramConfig};
+
+use meter::{Clock, Meter, MeterSnapshot, StdMeter, SystemClock};
+use metric::{Metric, MetricValue};
+use reservoir::{HdrReservoir, Reservoir, ReservoirHistogram};
+use snapshot::HistogramSnapshot;
+
+// Durations are recorded in nanoseconds, up to a minute.
+const MAX_DURATION_NS: u64 = 60 * 1000000000;
+
+#[derive(Debug)]
+pub struct TimerSnapshot {
+    pub meter: MeterSnapshot,
+    pub histogram: HistogramSnapshot
+}
+
+/// A Timer measures both the rate at which a piece of code is called and the distribution of
+/// its duration.
+///
+/// Durations are recorded in nanoseconds into a histogram backed by a reservoir, an HDR
+/// histogram by default, and every recorded duration marks a meter. Both the meter and the
+/// contexts returned by `start` read the time from the timer's clock.
+pub struct Timer<R = HdrReservoir, C: Clock = SystemClock> {
+    meter: StdMeter<C>,
+    histogram: ReservoirHistogram<R>,
+}
+
+impl Timer<HdrReservoir> {
+    pub fn new() -> Timer {
+        let histogram = Histogram::new(HistogramConfig {
+                                           max_memory: 0,
+                                           max_value: MAX_DURATION_NS,
+                                           precision: 3,
+                                       })
+                            .unwrap();
+
+        Timer::with_histogram(histogram)
+    }
+
+    /// Creates a new timer which records durations into the given histogram.
+    pub fn with_histogram(histogram: Histogram) -> Timer {
+        Timer::with_reservoir(HdrReservoir::new(histogram))
+    }
+}
+
+impl<R: Reservoir> Timer<R> {
+    /// Creates a new timer which records durations into the given reservoir.
+    pub fn with_reservoir(reservoir: R) -> Timer<R> {
+        Timer::with_reservoir_and_clock(reservoir, SystemClock)
+    }
+}
+
+impl<R: Reservoir, C: Clock> Timer<R, C> {
+    /// Creates a new timer which records durations into the given reservoir, and reads the time
+    /// from the given clock.
+    pub fn with_reservoir_and_clock(reservoir: R, clock: C) -> Timer<R, C> {
+        Timer {
+            meter: StdMeter::with_clock(clock),
+            histogram: ReservoirHistogram::new(reservoir),
+        }
+    }
+
+    /// Returns the clock the timer reads the time from.
+    pub fn clock(&self) -> &C {
+        self.meter.clock()
+    }
+
+    /// Records a duration.
+    ///
+    /// With the default HDR histogram, durations which exceed its maximum value are counted but
+    /// are not part of the distribution.
+    pub fn update(&self, duration: Duration) {
+        self.update_ns(nanos(duration));
+    }
+
+    /// Records a duration given in nanoseconds.
+    pub fn update_ns(&self, ns: u64) {
+        self.histogram.update(ns);
+        self.meter.mark(1);
+    }
+
+    /// Records a duration, correcting for coordinated omission when a duration was expected to
+    /// be recorded every `expected_interval`.
+    ///
+    /// The durations which were missed are added to the histogram, as described in
+    /// `snapshot::missed_values`, but only the recorded duration marks the meter.
+    pub fn update_with_expected_interval(&self, duration: Duration, expected_interval: Duration) {
+        self.update_ns_with_expected_interval(nanos(duration), nanos(expected_interval));
+    }
+
+    /// Records a duration given in nanoseconds, correcting for coordinated omission when a
+    /// duration was expected every `expected_interval` nanoseconds.
+    pub fn update_ns_with_expected_interval(&self, ns: u64, expected_interval: u64) {
+        self.histogram.update_with_expected_interval(ns, expected_interval);
+        self.meter.mark(1);
+    }
+
+    /// Times the given closure, returning its result.
+    pub fn time<F, T>(&self, f: F) -> T
+        where F: FnOnce() -> T
+    {
+        let _context = self.start();
+        f()
+    }
+
+    /// Starts timing, returning a context which records the elapsed time when dropped.
+    pub fn start(&self) -> Context<R, C> {
+        self.start_with_expected_interval(Duration::from_secs(0))
+    }
+
+    /// Starts timing, returning a context which records the elapsed time when dropped, corrected
+    /// for coordinated omission as with `update_with_expected_interval`.
+    pub fn start_with_expected_interval(&self, expected_interval: Duration) -> Context<R, C> {
+        Context {
+            timer: self,
+            start: self.clock().now(),
+            expected_interval: nanos(expected_interval),
+            stopped: false,
+        }
+    }
+
+    pub fn snapshot(&self) -> TimerSnapshot {
+        TimerSnapshot {
+            meter: self.meter.snapshot(),
+            histogram: self.histogram.snapshot(),
+        }
+    }
+}
+
+// Durations longer than u64::MAX nanoseconds, about 584 years, are clamped to it.
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs()
+            .saturating_mul(1000000000)
+            .saturating_add(duration.subsec_nanos() as u64)
+}
+
+impl<R: Reservoir, C: Clock> Metric for Timer<R, C> {
+    fn export_metric(&self) -> MetricValue {
+        MetricValue::Timer(self.snapshot())
+    }
+}
+
+/// A running measurement of a `Timer`.
+///
+/// The elapsed time is recorded when the context is stopped or dropped, whichever comes first.
+pub struct Context<'a, R: Reservoir + 'a = HdrReservoir, C: Clock + 'a = SystemClock> {
+    timer: &'a Timer<R, C>,
+    start: i64,
+    // In nanoseconds, zero if the recorded durations aren't corrected.
+    expected_interval: u64,
+    stopped: bool,
+}
+
+impl<'a, R: Reservoir, C: Clock> Context<'a, R, C> {
+    /// Records the elapsed time and returns it in nanoseconds.
+    pub fn stop(mut self) -> u64 {
+        self.record()
+    }
+
+    fn record(&mut self) -> u64 {
+        // A clock stepping backwards shouldn't produce a huge duration.
+        let elapsed = cmp::max(0, self.timer.clock().now() - self.start) as u64;
+
+        self.stopped = true;
+        self.timer.update_ns_with_expected_interval(elapsed, self.expected_interval);
+
+        elapsed
+    }
+}
+
+impl<'a, R: Reservoir, C: Clock> Drop for Context<'a, R, C> {
+    fn drop(&mut self) {
+        if !self.stopped {
+            self.record();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use metric::{Metric, MetricValue};
+    use meter::ManualClock;
+    use reservoir::{SlidingTimeWindowReservoir, SlidingWindowReservoir};
+    use super::*;
+
+    #[test]
+    fn update() {
+        let t = Timer::new();
+        t.update(Duration::from_millis(1));
+        t.update(Duration::from_millis(3));
+
+        let s = t.snapshot();
+
+        assert_eq!(2, s.meter.count);
+        assert_eq!(2, s.histogram.count());
+    }
+
+    #[test]
+    fn time_closure() {
+        let t = Timer::new();
+
+        assert_eq!(42, t.time(|| 42));
+        assert_eq!(1, t.snapshot().meter.count);
+    }
+
+    #[test]
+    fn context_records_once() {
+        let t = Timer::new();
+
+        {
+            let _c = t.start();
+        }
+        t.start().stop();
+
+        assert_eq!(2, t.snapshot().meter.count);
+    }
+
+    #[test]
+    fn too_long_still_marks() {
+        let t = Timer::new();
+        t.update(Duration::from_secs(120));
+
+        let s = t.snapshot();
+
+        // The histogram drops durations beyond a minute, from its count and sum as well.
+        assert_eq!(1, s.meter.count);
+        assert_eq!(0, s.histogram.count());
+        assert_eq!(0, s.histogram.sum());
+        assert_eq!(0, s.histogram.max());
+
+        t.update(Duration::from_millis(5));
+
+        let s = t.snapshot();
+
+        assert_eq!(2, s.meter.count);
+        assert_eq!(1, s.histogram.count());
+        assert_eq!(5000000, s.histogram.sum());
+        assert_eq!(s.histogram.sum() as f64, s.histogram.mean());
+        assert!((s.histogram.max() as i64 - 5000000).abs() <= 5000);
+    }
+
+    #[test]
+    fn export() {
+        let t = Timer::new();
+        t.update(Duration::from_millis(5));
+
+        match t.export_metric() {
+            MetricValue::Timer(x) => assert_eq!(1, x.meter.count),
+            _ => panic!("expected a timer"),
+        }
+    }
+
+    #[test]
+    fn expected_interval() {
+        let t = Timer::new();
+        t.update_with_expected_interval(Duration::from_millis(10), Duration::from_millis(3));
+
+        let s = t.snapshot();
+
+        assert_eq!(1, s.meter.count);
+        assert_eq!(3, s.histogram.count());
+        assert_eq!(10000000 + 7000000 + 4000000, s.histogram.sum());
+    }
+
+    #[test]
+    fn context_expected_interval() {
+        let t = Timer::new();
+        let expected = Duration::new(0, 100000);
+
+        {
+            let _c = t.start_with_expected_interval(expected);
+            ::std::thread::sleep(Duration::from_millis(1));
+        }
+        t.start_with_expected_interval(Duration::from_secs(60)).stop();
+
+        let s = t.snapshot();
+
+        assert_eq!(2, s.meter.count);
+        // The first measurement took at least ten expected intervals.
+        assert!(s.histogram.count() >= 11);
+    }
+
+    #[test]
+    fn huge_durations() {
+        assert_eq!(1500000000, nanos(Duration::from_millis(1500)));
+        assert_eq!(::std::u64::MAX, nanos(Duration::new(::std::u64::MAX, 0)));
+        assert_eq!(::std::u64::MAX, nanos(Duration::new(18446744073, 709551616)));
+
+        let t = Timer::with_reservoir(SlidingWindowReservoir::new(10));
+        t.update(Duration::new(::std::u64::MAX, 999999999));
+
+        assert_eq!(::std::u64::MAX, t.snapshot().histogram.max());
+    }
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new();
+        let t = Timer::with_reservoir_and_clock(SlidingWindowReservoir::new(10), clock.clone());
+
+        let c = t.start();
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(20000000, c.stop());
+
+        let c = t.start_with_expected_interval(Duration::from_millis(5));
+        clock.advance(Duration::from_millis(12));
+        drop(c);
+
+        // A clock stepping backwards records an empty duration.
+        let c = t.start();
+        clock.set(0);
+        assert_eq!(0, c.stop());
+
+        let s = t.snapshot();
+        assert_eq!(3, s.meter.count);
+        assert_eq!(vec![0, 7000000, 12000000, 20000000], s.histogram.values().to_vec());
+    }
+
+    #[test]
+    fn sliding_time_window() {
+        let t = Timer::with_reservoir(SlidingTimeWindowReservoir::new(Duration::from_secs(60)));
+        t.update(Duration::from_millis(1));
+        t.update(Duration::from_millis(2));
+
+        let s = t.snapshot();
+
+        assert_eq!(2, s.meter.count);
+        assert_eq!(2, s.histogram.count());
+        assert_eq!(3000000, s.histogram.sum());
+        assert_eq!(2000000, s.histogram.max());
+    }
+}